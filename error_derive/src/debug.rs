@@ -0,0 +1,117 @@
+use crate::generics;
+use crate::variant::Sub;
+use crate::ErrorEnum;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::Member;
+
+/// How the `Debug` impl is generated, set with `#[debug(...)]` on the enum.
+pub enum DebugStyle {
+    /// Forward to `Display`
+    Display,
+    /// `#[debug(structural)]`: print the variant and its fields, like `#[derive(Debug)]`
+    Structural,
+    /// `#[debug(none)]`: don't generate a `Debug` impl
+    None,
+}
+
+pub fn parse_style(attrs: &[syn::Attribute]) -> syn::Result<DebugStyle> {
+    let mut style = DebugStyle::Display;
+    for attr in attrs {
+        if !attr.path().is_ident("debug") {
+            continue;
+        }
+        let arg: proc_macro2::Ident = attr.parse_args()?;
+        style = if arg == "structural" {
+            DebugStyle::Structural
+        } else if arg == "none" {
+            DebugStyle::None
+        } else {
+            return Err(syn::Error::new(
+                arg.span(),
+                crate::errs::EXPECTED_DEBUG_STYLE,
+            ));
+        };
+    }
+    Ok(style)
+}
+
+pub(crate) fn make_impl(e: &ErrorEnum<'_>) -> TokenStream2 {
+    let ErrorEnum {
+        enum_name,
+        generics,
+        variants,
+        debug,
+        ..
+    } = e;
+    let (impl_generics, ty_generics, _) = generics.split_for_impl();
+
+    match debug {
+        DebugStyle::Display => {
+            let bounds = generics::display_bounds(e);
+            quote! {
+                impl #impl_generics ::core::fmt::Debug for #enum_name #ty_generics where #(#bounds,)* {
+                    fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                        ::core::fmt::Display::fmt(self, f)
+                    }
+                }
+            }
+        }
+        DebugStyle::Structural => {
+            let bounds = generics::debug_bounds(e);
+            let arms: Vec<_> = variants.iter().map(make_arm).collect();
+            quote! {
+                impl #impl_generics ::core::fmt::Debug for #enum_name #ty_generics where #(#bounds,)* {
+                    fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                        match self {
+                            #(#arms)*
+                            __unreachable => ::core::result::Result::Err(::core::fmt::Error),
+                        }
+                    }
+                }
+            }
+        }
+        DebugStyle::None => quote! {},
+    }
+}
+
+fn make_arm(v: &Sub<'_>) -> TokenStream2 {
+    let Sub {
+        ident,
+        is_tuple,
+        location,
+        all_field_members,
+        all_field_names,
+        ..
+    } = v;
+    let pat = v.pat();
+    let name = ident.to_string();
+
+    let fields = all_field_members
+        .iter()
+        .zip(all_field_names)
+        .map(|(member, binding)| {
+            // Locations are shown as `file:line:col`, like a panic message
+            let value = if location.as_ref().is_some_and(|l| &l.name == binding) {
+                quote! { &::core::format_args!("{}", #binding) }
+            } else {
+                quote! { #binding }
+            };
+            match member {
+                Member::Named(field) => {
+                    let field = field.to_string();
+                    quote! { .field(#field, #value) }
+                }
+                Member::Unnamed(_) => quote! { .field(#value) },
+            }
+        });
+
+    let builder = if *is_tuple {
+        quote! { debug_tuple }
+    } else {
+        quote! { debug_struct }
+    };
+    quote! {
+        #pat => f.#builder(#name) #(#fields)* .finish(),
+    }
+}
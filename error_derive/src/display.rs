@@ -1,5 +1,5 @@
+use crate::generics;
 use crate::variant::Sub;
-use crate::variant::Text;
 use crate::ErrorEnum;
 use proc_macro2::TokenStream as TokenStream2;
 use quote::quote;
@@ -9,44 +9,45 @@ pub(crate) fn make_impl(e: &ErrorEnum<'_>) -> TokenStream2 {
         enum_name,
         variants,
         is_top_level,
+        display_chain,
         ..
     } = e;
-    let arms: Vec<_> = variants.iter().map(make_arm).collect();
+    let arms: Vec<_> = variants
+        .iter()
+        .map(|v| make_arm(v, *display_chain, *is_top_level))
+        .collect();
+
+    let (impl_generics, ty_generics, _) = e.generics.split_for_impl();
+    let bounds = generics::display_bounds(e);
 
     let help = if *is_top_level {
         quote! {
-            ::core::fmt::Display::fmt("\n", f)?;
-            for error in (self as &dyn ::core::error::Error).sources() {
-                if let ::core::option::Option::Some(help) = ::core::error::request_value::<::error::Help>(error) {
-                    ::core::fmt::Display::fmt(&help, f)?;
-                }
-            }
+            ::error::__private::write_footer(f, self, ::core::option::Option::Some(self))?;
         }
     } else {
         quote! {}
     };
 
-    quote! {
-        impl ::core::fmt::Debug for #enum_name {
-            fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
-                ::core::fmt::Display::fmt(self, f)
-            }
+    // By default only the error's own message is printed, and the chain is left to a reporter.
+    let causes = if *display_chain {
+        // The sources print only their own messages too, so the chain is walked here
+        quote! {
+            ::error::__private::write_causes(f, self, ::core::option::Option::Some(self))?;
+            #help
         }
+    } else {
+        quote! {}
+    };
 
-        impl ::core::fmt::Display for #enum_name {
+    quote! {
+        impl #impl_generics ::core::fmt::Display for #enum_name #ty_generics where #(#bounds,)* {
             fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
                 #[allow(unused_variables)]
                 match self {
                    #(#arms)*
                    __unreachable => if true { return ::core::result::Result::Err(::core::fmt::Error) }
                 }
-
-                if let Some(source) = ::core::error::Error::source(self) {
-                    ::core::fmt::Display::fmt("Caused by: ", f)?;
-                    ::core::fmt::Display::fmt(source, f)?;
-                }
-
-                #help
+                #causes
 
                 Ok(())
             }
@@ -54,48 +55,68 @@ pub(crate) fn make_impl(e: &ErrorEnum<'_>) -> TokenStream2 {
     }
 }
 
-fn make_arm(v: &Sub<'_>) -> TokenStream2 {
+fn make_arm(v: &Sub<'_>, display_chain: bool, is_top_level: bool) -> TokenStream2 {
     let Sub {
-        enum_name,
-        name,
-        selector_field_names,
         error_text,
         location,
+        source,
+        code,
+        transparent,
         ..
     } = v;
+    let pat = v.pat();
+
+    // Like rustc, the top level leads with the code
+    let print_code = match code {
+        Some(code) if is_top_level => quote! {
+            ::core::write!(f, "error[{}]: ", #code)?;
+        },
+        _ => quote! {},
+    };
+
+    if let (true, Some(source)) = (transparent, source) {
+        let source = &source.name;
+        let newline = display_chain.then(|| quote! { ::core::fmt::Display::fmt("\n", f)?; });
+        return quote! {
+            #pat => {
+                #print_code
+                ::core::fmt::Display::fmt(#source, f)?;
+                #newline
+            },
+        };
+    }
+
     let error_text_maker: Vec<_> = error_text
         .iter()
-        .map(|Text { lit, args }| quote! { write!(f, #lit, #(#args),*)?; })
+        .map(|text| {
+            let write = text.write();
+            quote! { #write?; }
+        })
         .collect();
 
-    let print_location = if location.is_some() {
+    if !display_chain {
+        return quote! {
+            #pat => {
+                #(#error_text_maker)*
+            },
+        };
+    }
+
+    let print_location = if let Some(location) = location {
+        let location = &location.name;
         quote! {
-            if let #enum_name :: #name { location , .. } = _arm {
-                write!(f, " (at {})", location)?;
-            }
+            ::core::write!(f, " (at {})", #location)?;
         }
     } else {
         quote! {}
     };
 
-    match selector_field_names.len() {
-        0 => {
-            quote! {
-                _arm @ #enum_name :: #name { .. } => {
-                    #(#error_text_maker)*
-                    #print_location
-                    ::core::fmt::Display::fmt("\n", f)?;
-                },
-            }
-        }
-        _ => {
-            quote! {
-                _arm @ #enum_name :: #name { #(#selector_field_names),* , .. } => {
-                    #(#error_text_maker)*
-                    #print_location
-                    ::core::fmt::Display::fmt("\n", f)?;
-                },
-            }
-        }
+    quote! {
+        #pat => {
+            #print_code
+            #(#error_text_maker)*
+            #print_location
+            ::core::fmt::Display::fmt("\n", f)?;
+        },
     }
 }
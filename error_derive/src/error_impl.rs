@@ -1,3 +1,6 @@
+use crate::generics;
+use crate::variant::field_pat;
+use crate::variant::Binding;
 use crate::variant::Sub;
 use crate::variant::Text;
 use crate::ErrorEnum;
@@ -12,9 +15,100 @@ pub(crate) fn make_impl(e: &ErrorEnum<'_>) -> TokenStream2 {
     } = e;
 
     let source_arms: Vec<_> = variants.iter().map(make_source_arm).collect();
-    let provide_arms: Vec<_> = variants.iter().map(make_provide_arm).collect();
+
+    let provide = if cfg!(feature = "nightly") {
+        let provide_arms: Vec<_> = variants.iter().map(make_provide_arm).collect();
+        // Providing `dyn Diagnostic` needs `Self: 'static`, which only holds without lifetimes or
+        // type parameters. Other errors are only reachable through `diagnostic_source`.
+        let is_static = e
+            .generics
+            .params
+            .iter()
+            .all(|param| matches!(param, syn::GenericParam::Const(_)));
+        let provide_diagnostic = is_static.then(|| {
+            quote! { request.provide_ref::<dyn ::error::Diagnostic>(self); }
+        });
+        quote! {
+            fn provide<'__request>(&'__request self, request: &mut ::core::error::Request<'__request>) {
+                #provide_diagnostic
+                match self {
+                    #(#provide_arms)*
+                    __unreachable => {}
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    let write_message_arms: Vec<_> = variants.iter().map(make_write_message_arm).collect();
+    let write_help_arms: Vec<_> = variants
+        .iter()
+        .map(|v| make_write_arm(v, TextKind::Help))
+        .collect();
+    let write_note_arms: Vec<_> = variants
+        .iter()
+        .map(|v| make_write_arm(v, TextKind::Note))
+        .collect();
+    let help_arms: Vec<_> = variants
+        .iter()
+        .map(|v| make_text_arm(v, TextKind::Help))
+        .collect();
+    let note_arms: Vec<_> = variants
+        .iter()
+        .map(|v| make_text_arm(v, TextKind::Note))
+        .collect();
+    let location_arms: Vec<_> = variants.iter().map(make_location_arm).collect();
+    let code_arms: Vec<_> = variants.iter().map(make_code_arm).collect();
+    let backtrace = if cfg!(feature = "std") {
+        let backtrace_arms: Vec<_> = variants.iter().map(make_backtrace_arm).collect();
+        quote! {
+            fn backtrace(&self) -> ::core::option::Option<&::error::__private::Backtrace> {
+                match self {
+                    #(#backtrace_arms)*
+                    __unreachable => None,
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+    let trace = if cfg!(feature = "alloc") {
+        let trace_arms: Vec<_> = variants.iter().map(|v| make_trace_arm(v, false)).collect();
+        let trace_mut_arms: Vec<_> = variants.iter().map(|v| make_trace_arm(v, true)).collect();
+        quote! {
+            fn trace(&self) -> ::core::option::Option<&::error::Trace> {
+                match self {
+                    #(#trace_arms)*
+                    __unreachable => None,
+                }
+            }
+
+            fn trace_mut(&mut self) -> ::core::option::Option<&mut ::error::Trace> {
+                match self {
+                    #(#trace_mut_arms)*
+                    __unreachable => None,
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+    let diagnostic_source_arms: Vec<_> = variants.iter().map(make_diagnostic_source_arm).collect();
+
+    let serde = if cfg!(feature = "serde") {
+        make_serde(e)
+    } else {
+        quote! {}
+    };
+
+    let code_impl = make_code_impl(e);
+
+    let (impl_generics, ty_generics, _) = e.generics.split_for_impl();
+    let bounds = generics::error_bounds(e);
+    let diagnostic_bounds = generics::diagnostic_bounds(e);
     quote! {
-        impl ::core::error::Error for #enum_name {
+        impl #impl_generics ::core::error::Error for #enum_name #ty_generics where #(#bounds,)* {
             fn source(&self) -> ::core::option::Option<&(dyn ::core::error::Error + 'static)> {
                 match self {
                     #(#source_arms)*
@@ -22,68 +116,595 @@ pub(crate) fn make_impl(e: &ErrorEnum<'_>) -> TokenStream2 {
                 }
             }
 
-            fn provide<'a>(&'a self, request: &mut ::core::error::Request<'a>) {
+            #provide
+        }
+
+        impl #impl_generics ::error::Diagnostic for #enum_name #ty_generics where #(#diagnostic_bounds,)* {
+            fn write_message(&self, f: &mut dyn ::core::fmt::Write) -> ::core::fmt::Result {
                 #[allow(unused_variables)]
                 match self {
-                    #(#provide_arms)*
-                    __unreachable => {}
+                    #(#write_message_arms)*
+                    __unreachable => Ok(()),
+                }
+            }
+
+            fn write_help_line(&self, index: usize, f: &mut dyn ::core::fmt::Write) -> ::core::fmt::Result {
+                #[allow(unused_variables)]
+                match self {
+                    #(#write_help_arms)*
+                    __unreachable => Ok(()),
+                }
+            }
+
+            fn write_note_line(&self, index: usize, f: &mut dyn ::core::fmt::Write) -> ::core::fmt::Result {
+                #[allow(unused_variables)]
+                match self {
+                    #(#write_note_arms)*
+                    __unreachable => Ok(()),
+                }
+            }
+
+            fn help(&self) -> ::core::option::Option<::error::Help<'_>> {
+                match self {
+                    #(#help_arms)*
+                    __unreachable => None,
                 }
             }
+
+            fn note(&self) -> ::core::option::Option<::error::Note<'_>> {
+                match self {
+                    #(#note_arms)*
+                    __unreachable => None,
+                }
+            }
+
+            fn location(&self) -> ::core::option::Option<&'static ::core::panic::Location<'static>> {
+                match self {
+                    #(#location_arms)*
+                    __unreachable => None,
+                }
+            }
+
+            fn code(&self) -> ::core::option::Option<::error::Code> {
+                match self {
+                    #(#code_arms)*
+                    __unreachable => None,
+                }
+            }
+
+            #backtrace
+
+            #trace
+
+            fn diagnostic_source(&self) -> ::core::option::Option<&dyn ::error::Diagnostic> {
+                match self {
+                    #(#diagnostic_source_arms)*
+                    __unreachable => None,
+                }
+            }
+
+            #serde
         }
+
+        #code_impl
     }
 }
 
-fn make_source_arm(v: &Sub<'_>) -> TokenStream2 {
+/// `field` as an `Option<&dyn Diagnostic>`, which is `None` if its type is not a `Diagnostic`.
+fn as_diagnostic(field: &proc_macro2::Ident) -> TokenStream2 {
+    quote! {
+        {
+            use ::error::__private::{AsDiagnostic as _, NotDiagnostic as _};
+            (&::error::__private::Wrap(#field)).as_diagnostic()
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum TextKind {
+    Help,
+    Note,
+}
+
+impl TextKind {
+    fn text<'a>(self, v: &'a Sub<'_>) -> &'a [Text] {
+        match self {
+            TextKind::Help => &v.help_text,
+            TextKind::Note => &v.note_text,
+        }
+    }
+
+    fn ty(self) -> TokenStream2 {
+        match self {
+            TextKind::Help => quote! { ::error::Help },
+            TextKind::Note => quote! { ::error::Note },
+        }
+    }
+
+    /// The `Diagnostic` method that returns it
+    fn method(self) -> TokenStream2 {
+        match self {
+            TextKind::Help => quote! { help },
+            TextKind::Note => quote! { note },
+        }
+    }
+
+    /// The `Diagnostic` method that writes one of its lines
+    fn write_method(self) -> TokenStream2 {
+        match self {
+            TextKind::Help => quote! { write_help_line },
+            TextKind::Note => quote! { write_note_line },
+        }
+    }
+}
+
+/// Writes the message of a variant, without the code, location and chain that `Display` might
+/// add.
+fn make_write_message_arm(v: &Sub<'_>) -> TokenStream2 {
     let Sub {
-        enum_name,
-        name,
+        error_text,
+        source,
+        transparent,
+        ..
+    } = v;
+    let pat = v.pat();
+
+    if let (true, Some(source)) = (transparent, source) {
+        let name = &source.name;
+        let source = as_diagnostic(name);
+        return quote! {
+            #pat => match #source {
+                ::core::option::Option::Some(source) => source.write_message(f),
+                ::core::option::Option::None => ::core::write!(f, "{}", #name),
+            },
+        };
+    }
+
+    let writes = error_text.iter().map(|text| {
+        let write = text.write();
+        quote! { #write?; }
+    });
+    quote! {
+        #pat => {
+            #(#writes)*
+            Ok(())
+        },
+    }
+}
+
+/// Writes one line of the help or note of a variant, for the `Diagnostic` impl.
+fn make_write_arm(v: &Sub<'_>, kind: TextKind) -> TokenStream2 {
+    let Sub {
+        path,
         source,
+        transparent,
         ..
     } = v;
-    if source.is_some() {
+    let pat = v.pat();
+    let text = kind.text(v);
+    let method = kind.write_method();
+
+    if !text.is_empty() {
+        let lines = text.iter().enumerate().map(|(i, text)| {
+            let write = text.write();
+            quote! {
+                #i => #write,
+            }
+        });
         quote! {
-            #enum_name :: #name { source, .. } => { ::core::option::Option::Some(source) },
+            #pat => match index {
+                #(#lines)*
+                _ => Ok(()),
+            },
+        }
+    } else if let (true, Some(source)) = (transparent, source) {
+        let source = as_diagnostic(&source.name);
+        quote! {
+            #pat => match #source {
+                ::core::option::Option::Some(source) => source.#method(index, f),
+                ::core::option::Option::None => Ok(()),
+            },
         }
     } else {
         quote! {
-            #enum_name :: #name { .. } => ::core::option::Option::None,
+            #path { .. } => Ok(()),
         }
     }
 }
 
-fn make_provide_arm(v: &Sub<'_>) -> TokenStream2 {
+/// The help or note of a variant, which borrows the error to format it later.
+fn make_text_arm(v: &Sub<'_>, kind: TextKind) -> TokenStream2 {
     let Sub {
-        enum_name,
-        name,
-        all_field_names,
-        help_text,
+        path,
+        source,
+        transparent,
         ..
     } = v;
-    if !help_text.is_empty() {
-        let help_formatter: Vec<_> = help_text
-            .iter()
-            .map(|Text { lit, args }| {
-                quote! {
-                   write!(&mut msg, "Help: ").unwrap();
-                   writeln!(&mut msg, #lit, #(#args),*).unwrap();
-                }
-            })
-            .collect();
+    let origin = v.origin();
+    let ty = kind.ty();
+    let method = kind.method();
+    let text = kind.text(v);
+
+    if !text.is_empty() {
+        let len = text.len();
+        quote! {
+            #path { .. } => ::core::option::Option::Some(#ty::new(self, #origin, #len)),
+        }
+    } else if let (true, Some(Binding { member, name, .. })) = (transparent, source) {
+        let field = field_pat(member, name);
+        let source = as_diagnostic(name);
+        quote! {
+            #path { #field, .. } => #source.and_then(|source| source.#method()),
+        }
+    } else {
         quote! {
-            #enum_name :: #name { #(#all_field_names),* } => {
-                request.provide_value_with::<::error::Help>(|| {
-                    use core::fmt::Write;
+            #path { .. } => ::core::option::Option::None,
+        }
+    }
+}
+
+/// The `Diagnostic` methods for the `serde` feature.
+fn make_serde(e: &ErrorEnum<'_>) -> TokenStream2 {
+    let mut enum_name_arms = Vec::new();
+    let mut variant_name_arms = Vec::new();
+    let mut fields_arms = Vec::new();
+    for v in &e.variants {
+        let Sub {
+            enum_name,
+            ident,
+            path,
+            source,
+            transparent,
+            serialize,
+            ..
+        } = v;
+
+        if let (true, Some(Binding { member, name, .. })) = (transparent, source) {
+            let field = field_pat(member, name);
+            let source = as_diagnostic(name);
+            enum_name_arms.push(quote! {
+                #path { #field, .. } => #source.and_then(|source| source.enum_name()),
+            });
+            variant_name_arms.push(quote! {
+                #path { #field, .. } => #source.and_then(|source| source.variant_name()),
+            });
+            fields_arms.push(quote! {
+                #path { #field, .. } => {
+                    if let ::core::option::Option::Some(source) = #source {
+                        fields = source.fields();
+                    }
+                },
+            });
+            continue;
+        }
+
+        let enum_name = enum_name.to_string();
+        enum_name_arms.push(quote! {
+            #path { .. } => ::core::option::Option::Some(#enum_name),
+        });
+        let variant_name = if e.is_struct {
+            quote! { ::core::option::Option::None }
+        } else {
+            let ident = ident.to_string();
+            quote! { ::core::option::Option::Some(#ident) }
+        };
+        variant_name_arms.push(quote! {
+            #path { .. } => #variant_name,
+        });
 
-                    let mut msg = ::std::string::String::new();
-                    #(#help_formatter)*
+        let inserts = serialize.iter().map(|Binding { member, name, .. }| {
+            let key = match member {
+                syn::Member::Named(ident) => ident.to_string(),
+                syn::Member::Unnamed(index) => index.index.to_string(),
+            };
+            // Failing to serialize a field should not lose the rest of the report
+            quote! {
+                fields.insert(
+                    ::error::__private::json::String::from(#key),
+                    ::error::__private::json::to_value(#name).unwrap_or_default(),
+                );
+            }
+        });
+        let pat = v.pat();
+        fields_arms.push(quote! {
+            #pat => {
+                #(#inserts)*
+            },
+        });
+    }
+
+    quote! {
+        fn enum_name(&self) -> ::core::option::Option<&'static str> {
+            match self {
+                #(#enum_name_arms)*
+                __unreachable => None,
+            }
+        }
+
+        fn variant_name(&self) -> ::core::option::Option<&'static str> {
+            match self {
+                #(#variant_name_arms)*
+                __unreachable => None,
+            }
+        }
+
+        fn fields(&self) -> ::error::__private::json::Map {
+            let mut fields = ::error::__private::json::Map::new();
+            #[allow(unused_variables)]
+            match self {
+                #(#fields_arms)*
+                __unreachable => {}
+            }
+            fields
+        }
+    }
+}
 
-                    ::error::Help::new(msg)
-                });
+/// An inherent `code()`, when every variant has a code.
+///
+/// Transparent variants without one forward to the error they wrap, which might not have a code,
+/// so then `code()` returns an `Option`.
+fn make_code_impl(e: &ErrorEnum<'_>) -> TokenStream2 {
+    if e.variants.is_empty() || e.variants.iter().all(|v| v.code.is_none()) {
+        return quote! {};
+    }
+    let forwards = e.variants.iter().any(|v| v.code.is_none());
+    let arms = e.variants.iter().map(|v| {
+        let path = &v.path;
+        match (&v.code, &v.source) {
+            (Some(code), _) if forwards => quote! {
+                #path { .. } => ::core::option::Option::Some(#code),
+            },
+            (Some(code), _) => quote! {
+                #path { .. } => #code,
+            },
+            // `check_codes` makes sure that only transparent variants have no code
+            (None, Some(Binding { member, name, .. })) => {
+                let field = field_pat(member, name);
+                let source = as_diagnostic(name);
+                quote! {
+                    #path { #field, .. } => #source
+                        .and_then(|source| source.code())
+                        .map(|code| code.as_str()),
+                }
+            }
+            (None, None) => quote! {
+                #path { .. } => ::core::option::Option::None,
             },
         }
+    });
+    let ret = if forwards {
+        quote! { ::core::option::Option<&'static str> }
+    } else {
+        quote! { &'static str }
+    };
+    let enum_name = e.enum_name;
+    let (impl_generics, ty_generics, where_clause) = e.generics.split_for_impl();
+    quote! {
+        impl #impl_generics #enum_name #ty_generics #where_clause {
+            /// The stable identifier of this error, from `#[code = "..."]`.
+            pub fn code(&self) -> #ret {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+    }
+}
+
+fn make_code_arm(v: &Sub<'_>) -> TokenStream2 {
+    let Sub {
+        path,
+        source,
+        code,
+        transparent,
+        ..
+    } = v;
+    match (code, source) {
+        (Some(code), _) => quote! {
+            #path { .. } => ::core::option::Option::Some(::error::Code::new(#code)),
+        },
+        (None, Some(Binding { member, name, .. })) if *transparent => {
+            let field = field_pat(member, name);
+            let source = as_diagnostic(name);
+            quote! {
+                #path { #field, .. } => #source.and_then(|source| source.code()),
+            }
+        }
+        _ => quote! {
+            #path { .. } => ::core::option::Option::None,
+        },
+    }
+}
+
+fn make_location_arm(v: &Sub<'_>) -> TokenStream2 {
+    let Sub {
+        path,
+        source,
+        location,
+        transparent,
+        ..
+    } = v;
+    match (location, source) {
+        (Some(Binding { member, name, .. }), _) => {
+            let field = field_pat(member, name);
+            quote! {
+                #path { #field, .. } => ::core::option::Option::Some(*#name),
+            }
+        }
+        (None, Some(Binding { member, name, .. })) if *transparent => {
+            let field = field_pat(member, name);
+            let source = as_diagnostic(name);
+            quote! {
+                #path { #field, .. } => #source.and_then(|source| source.location()),
+            }
+        }
+        _ => quote! {
+            #path { .. } => ::core::option::Option::None,
+        },
+    }
+}
+
+fn make_backtrace_arm(v: &Sub<'_>) -> TokenStream2 {
+    let Sub {
+        path,
+        source,
+        backtrace,
+        transparent,
+        ..
+    } = v;
+    match (backtrace, source) {
+        (Some(Binding { member, name, .. }), _) => {
+            let field = field_pat(member, name);
+            quote! {
+                #path { #field, .. } => ::core::option::Option::Some(#name),
+            }
+        }
+        (None, Some(Binding { member, name, .. })) if *transparent => {
+            let field = field_pat(member, name);
+            let source = as_diagnostic(name);
+            quote! {
+                #path { #field, .. } => #source.and_then(|source| source.backtrace()),
+            }
+        }
+        _ => quote! {
+            #path { .. } => ::core::option::Option::None,
+        },
+    }
+}
+
+/// `trace` or, with `mutable`, `trace_mut`.
+fn make_trace_arm(v: &Sub<'_>, mutable: bool) -> TokenStream2 {
+    let Sub {
+        path,
+        source,
+        trace,
+        transparent,
+        ..
+    } = v;
+    match (trace, source) {
+        (Some(Binding { member, name, .. }), _) => {
+            let field = field_pat(member, name);
+            quote! {
+                #path { #field, .. } => ::core::option::Option::Some(#name),
+            }
+        }
+        (None, Some(Binding { member, name, .. })) if *transparent => {
+            let field = field_pat(member, name);
+            let arm = if mutable {
+                quote! {
+                    {
+                        use ::error::__private::{IntoDiagnosticMut as _, NotDiagnosticMut as _};
+                        ::error::__private::WrapMut(#name).into_diagnostic_mut()
+                    }
+                    .and_then(|source| source.trace_mut())
+                }
+            } else {
+                let source = as_diagnostic(name);
+                quote! { #source.and_then(|source| source.trace()) }
+            };
+            quote! {
+                #path { #field, .. } => #arm,
+            }
+        }
+        _ => quote! {
+            #path { .. } => ::core::option::Option::None,
+        },
+    }
+}
+
+fn make_diagnostic_source_arm(v: &Sub<'_>) -> TokenStream2 {
+    let Sub {
+        path,
+        source,
+        transparent,
+        ..
+    } = v;
+    match source {
+        Some(Binding { member, name, .. }) => {
+            let field = field_pat(member, name);
+            let source = as_diagnostic(name);
+            if *transparent {
+                quote! {
+                    #path { #field, .. } => #source.and_then(|source| source.diagnostic_source()),
+                }
+            } else {
+                quote! {
+                    #path { #field, .. } => #source,
+                }
+            }
+        }
+        None => quote! {
+            #path { .. } => ::core::option::Option::None,
+        },
+    }
+}
+
+fn make_source_arm(v: &Sub<'_>) -> TokenStream2 {
+    let Sub {
+        path,
+        source,
+        transparent,
+        ..
+    } = v;
+    if let Some(Binding { member, name, .. }) = source {
+        let field = field_pat(member, name);
+        if *transparent {
+            return quote! {
+                #path { #field, .. } => ::core::error::Error::source(#name),
+            };
+        }
+        quote! {
+            #path { #field, .. } => { ::core::option::Option::Some(#name) },
+        }
     } else {
         quote! {
-            #enum_name :: #name { .. } => {},
+            #path { .. } => ::core::option::Option::None,
         }
     }
 }
+
+fn make_provide_arm(v: &Sub<'_>) -> TokenStream2 {
+    let Sub {
+        path,
+        source,
+        location,
+        backtrace,
+        trace,
+        code,
+        transparent,
+        ..
+    } = v;
+    let mut fields = Vec::new();
+    let provide_code = code.as_ref().map(|code| {
+        quote! { request.provide_value(::error::Code::new(#code)); }
+    });
+    let provide_location = location.as_ref().map(|Binding { member, name, .. }| {
+        fields.push(field_pat(member, name));
+        quote! { request.provide_ref::<::core::panic::Location<'static>>(#name); }
+    });
+    let provide_backtrace = backtrace.as_ref().map(|Binding { member, name, .. }| {
+        fields.push(field_pat(member, name));
+        quote! { request.provide_ref::<::error::__private::Backtrace>(#name); }
+    });
+    let provide_trace = trace.as_ref().map(|Binding { member, name, .. }| {
+        fields.push(field_pat(member, name));
+        quote! { request.provide_ref::<::error::Trace>(#name); }
+    });
+    let forward = match source {
+        Some(Binding { member, name, .. }) if *transparent => {
+            fields.push(field_pat(member, name));
+            Some(quote! { ::core::error::Error::provide(#name, request); })
+        }
+        _ => None,
+    };
+    quote! {
+        #path { #(#fields,)* .. } => {
+            #provide_code
+            #provide_location
+            #provide_backtrace
+            #provide_trace
+            #forward
+        },
+    }
+}
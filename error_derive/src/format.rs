@@ -0,0 +1,268 @@
+//! Parsing of the format strings in `#[error]`, `#[help]` and `#[note]`.
+//!
+//! Placeholders are `{arg}` or `{arg:spec}`, where `arg` is a field, a tuple index, nothing for the
+//! next tuple index, or an expression. The spec is that of `core::fmt`, except that a width or
+//! precision can also be an argument of its own in braces, like `{name:>{width}}`.
+
+use std::fmt::Write;
+use std::ops::Range;
+
+/// A malformed format string, with the bytes of it that are at fault.
+#[derive(Debug)]
+pub struct FmtError {
+    pub message: &'static str,
+    pub range: Range<usize>,
+}
+
+impl FmtError {
+    fn new(message: &'static str, range: Range<usize>) -> Self {
+        Self { message, range }
+    }
+}
+
+/// The argument of a placeholder, or of a width or precision, and where it starts in the string.
+pub type Arg = (String, usize);
+
+/// Rewrites `s` with every argument taken out, so that it can be given to `format_args!` with the
+/// arguments after it.
+///
+/// The arguments of the placeholders come first in order, and then those of widths and
+/// precisions, which the placeholders refer to by index.
+pub fn fmt_parse(s: &str) -> Result<(String, Vec<Arg>), FmtError> {
+    let mut pieces = Vec::new();
+    let mut i = 0;
+    let mut text = 0;
+    while i < s.len() {
+        match s.as_bytes()[i] {
+            b'{' if s[i + 1..].starts_with('{') => i += 2,
+            b'}' if s[i + 1..].starts_with('}') => i += 2,
+            b'{' => {
+                pieces.push(Piece::Text(&s[text..i]));
+                let (placeholder, end) = placeholder(s, i)?;
+                pieces.push(Piece::Placeholder(placeholder));
+                i = end;
+                text = i;
+            }
+            b'}' => return Err(FmtError::new(crate::errs::UNMATCHED_BRACE, i..i + 1)),
+            _ => i += s[i..].chars().next().map_or(1, char::len_utf8),
+        }
+    }
+    pieces.push(Piece::Text(&s[text..]));
+
+    let values: Vec<Arg> = pieces
+        .iter()
+        .filter_map(|piece| match piece {
+            Piece::Placeholder(placeholder) => Some(placeholder.arg.clone()),
+            Piece::Text(_) => None,
+        })
+        .collect();
+    let mut counts = Vec::new();
+    let mut out = String::new();
+    for piece in pieces {
+        match piece {
+            Piece::Text(text) => out.push_str(text),
+            Piece::Placeholder(Placeholder { spec, .. }) => {
+                out.push('{');
+                if let Some(spec) = spec {
+                    out.push(':');
+                    out.push_str(spec.flags);
+                    if let Some(width) = spec.width {
+                        write_count(&mut out, width, values.len(), &mut counts);
+                    }
+                    if let Some(precision) = spec.precision {
+                        out.push('.');
+                        write_count(&mut out, precision, values.len(), &mut counts);
+                    }
+                    out.push_str(spec.ty);
+                }
+                out.push('}');
+            }
+        }
+    }
+    let mut args = values;
+    args.extend(counts);
+    Ok((out, args))
+}
+
+/// Writes a width or precision, as an index into the arguments if it is one.
+fn write_count(out: &mut String, count: Count<'_>, values: usize, counts: &mut Vec<Arg>) {
+    match count {
+        Count::Literal(n) => out.push_str(n),
+        Count::Arg(arg) => {
+            let _ = write!(out, "{}$", values + counts.len());
+            counts.push(arg);
+        }
+    }
+}
+
+enum Piece<'a> {
+    /// Text as written, with escaped braces still doubled
+    Text(&'a str),
+    Placeholder(Placeholder<'a>),
+}
+
+struct Placeholder<'a> {
+    arg: Arg,
+    spec: Option<Spec<'a>>,
+}
+
+struct Spec<'a> {
+    /// Fill, alignment, sign, `#` and `0`, as written
+    flags: &'a str,
+    width: Option<Count<'a>>,
+    precision: Option<Count<'a>>,
+    ty: &'a str,
+}
+
+enum Count<'a> {
+    Literal(&'a str),
+    Arg(Arg),
+}
+
+/// The placeholder whose `{` is at `start`, and where it ends.
+fn placeholder(s: &str, start: usize) -> Result<(Placeholder<'_>, usize), FmtError> {
+    let unclosed = || FmtError::new(crate::errs::UNCLOSED_BRACE, start..s.len());
+    let arg_start = start + 1;
+    let arg_end = arg_end(s, arg_start).ok_or_else(unclosed)?;
+    let arg = (String::from(&s[arg_start..arg_end]), arg_start);
+    if s.as_bytes()[arg_end] == b'}' {
+        return Ok((Placeholder { arg, spec: None }, arg_end + 1));
+    }
+
+    let mut i = arg_end + 1;
+    let flags_start = i;
+    let rest = &s[i..];
+    let mut chars = rest.chars();
+    let first = chars.next();
+    let second = chars.next();
+    if second.is_some_and(is_align) && first != Some('}') {
+        i += first.map_or(0, char::len_utf8) + 1;
+    } else if first.is_some_and(is_align) {
+        i += 1;
+    }
+    if matches!(s.as_bytes().get(i), Some(b'+' | b'-')) {
+        i += 1;
+    }
+    if s[i..].starts_with('#') {
+        i += 1;
+    }
+    if s[i..].starts_with('0') && !s[i + 1..].starts_with('$') {
+        i += 1;
+    }
+    let flags = &s[flags_start..i];
+
+    let width = count(s, &mut i)?;
+    let precision = if s[i..].starts_with('.') {
+        i += 1;
+        if s[i..].starts_with('*') {
+            return Err(FmtError::new(crate::errs::STAR_PRECISION, i..i + 1));
+        }
+        match count(s, &mut i)? {
+            Some(count) => Some(count),
+            None => return Err(FmtError::new(crate::errs::EXPECTED_PRECISION, i - 1..i)),
+        }
+    } else {
+        None
+    };
+
+    let ty_start = i;
+    let ty_end = ty_start + s[ty_start..].find('}').ok_or_else(unclosed)?;
+    let ty = &s[ty_start..ty_end];
+    if !matches!(
+        ty,
+        "" | "?" | "x?" | "X?" | "x" | "X" | "o" | "b" | "e" | "E" | "p"
+    ) {
+        return Err(FmtError::new(
+            crate::errs::BAD_FORMAT_SPEC,
+            ty_start..ty_end,
+        ));
+    }
+
+    let spec = Spec {
+        flags,
+        width,
+        precision,
+        ty,
+    };
+    Ok((
+        Placeholder {
+            arg,
+            spec: Some(spec),
+        },
+        ty_end + 1,
+    ))
+}
+
+fn is_align(c: char) -> bool {
+    matches!(c, '<' | '^' | '>')
+}
+
+/// Where the argument that starts at `start` ends, at a `:` or `}` outside of any brackets and
+/// string literals, or `None` if it never does.
+fn arg_end(s: &str, start: usize) -> Option<usize> {
+    let bytes = s.as_bytes();
+    let mut depth = 0usize;
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'(' | b'[' | b'{' => depth += 1,
+            b')' | b']' => depth = depth.saturating_sub(1),
+            b'}' if depth == 0 => return Some(i),
+            b'}' => depth -= 1,
+            // `::` is a path, not a spec
+            b':' if bytes.get(i + 1) == Some(&b':') => i += 1,
+            b':' if depth == 0 => return Some(i),
+            b'"' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// A width or precision at `i`, which is moved past it.
+fn count<'a>(s: &'a str, i: &mut usize) -> Result<Option<Count<'a>>, FmtError> {
+    let start = *i;
+    let rest = &s[start..];
+    if rest.starts_with('{') {
+        let arg_start = start + 1;
+        let unclosed = || FmtError::new(crate::errs::UNCLOSED_BRACE, start..s.len());
+        let arg_end = arg_end(s, arg_start).ok_or_else(unclosed)?;
+        if s.as_bytes()[arg_end] != b'}' {
+            return Err(FmtError::new(
+                crate::errs::BAD_FORMAT_SPEC,
+                arg_end..arg_end + 1,
+            ));
+        }
+        if arg_end == arg_start {
+            return Err(FmtError::new(crate::errs::EMPTY_COUNT, start..arg_end + 1));
+        }
+        *i = arg_end + 1;
+        return Ok(Some(Count::Arg((
+            String::from(&s[arg_start..arg_end]),
+            arg_start,
+        ))));
+    }
+
+    let len = rest
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(rest.len());
+    let word = &rest[..len];
+    if len > 0 && rest[len..].starts_with('$') {
+        *i += len + 1;
+        return Ok(Some(Count::Arg((String::from(word), start))));
+    }
+    let digits = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    if digits > 0 {
+        *i += digits;
+        return Ok(Some(Count::Literal(&rest[..digits])));
+    }
+    Ok(None)
+}
@@ -0,0 +1,226 @@
+use crate::debug::DebugStyle;
+use crate::ErrorEnum;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use std::collections::HashSet;
+use syn::visit::Visit;
+use syn::GenericParam;
+use syn::Generics;
+use syn::Type;
+
+/// Every identifier and lifetime that is mentioned in a type.
+#[derive(Default)]
+struct Mentions {
+    idents: HashSet<proc_macro2::Ident>,
+    lifetimes: HashSet<syn::Lifetime>,
+}
+
+impl<'ast> Visit<'ast> for Mentions {
+    fn visit_path(&mut self, path: &'ast syn::Path) {
+        if let Some(segment) = path.segments.first() {
+            if path.leading_colon.is_none() {
+                self.idents.insert(segment.ident.clone());
+            }
+        }
+        syn::visit::visit_path(self, path);
+    }
+
+    fn visit_lifetime(&mut self, lifetime: &'ast syn::Lifetime) {
+        self.lifetimes.insert(lifetime.clone());
+    }
+}
+
+impl Mentions {
+    fn of<'a>(tys: impl IntoIterator<Item = &'a Type>) -> Self {
+        let mut mentions = Mentions::default();
+        for ty in tys {
+            mentions.visit_type(ty);
+        }
+        mentions
+    }
+
+    fn contains(&self, param: &GenericParam) -> bool {
+        match param {
+            GenericParam::Type(param) => self.idents.contains(&param.ident),
+            GenericParam::Const(param) => self.idents.contains(&param.ident),
+            GenericParam::Lifetime(param) => self.lifetimes.contains(&param.lifetime),
+        }
+    }
+}
+
+/// Whether a bound on `ty` should be inferred, which is when it mentions any of the type
+/// parameters in `generics`.
+///
+/// Types that mention the error type itself are skipped, as the bound would be recursive.
+fn needs_bound(e: &ErrorEnum<'_>, ty: &Type) -> bool {
+    let mentions = Mentions::of([ty]);
+    !mentions.idents.contains(e.enum_name)
+        && e.generics
+            .type_params()
+            .any(|param| mentions.idents.contains(&param.ident))
+}
+
+/// Splits `generics` into the parameters that are used by `tys` and those that are not.
+///
+/// Defaults are stripped from the unused parameters, so they can be declared on a function.
+pub fn split_used<'a>(
+    generics: &Generics,
+    tys: impl IntoIterator<Item = &'a Type>,
+) -> (Generics, Generics) {
+    let mentions = Mentions::of(tys);
+
+    let mut used = Generics::default();
+    let mut unused = Generics::default();
+    for param in &generics.params {
+        if mentions.contains(param) {
+            used.params.push(param.clone());
+        } else {
+            let mut param = param.clone();
+            match &mut param {
+                GenericParam::Type(param) => {
+                    param.eq_token = None;
+                    param.default = None;
+                }
+                GenericParam::Const(param) => {
+                    param.eq_token = None;
+                    param.default = None;
+                }
+                GenericParam::Lifetime(_) => {}
+            }
+            unused.params.push(param);
+        }
+    }
+
+    // Only keep predicates that are entirely about the used parameters
+    let predicates: Vec<_> = generics
+        .where_clause
+        .iter()
+        .flat_map(|where_clause| &where_clause.predicates)
+        .filter(|predicate| {
+            let mut predicate_mentions = Mentions::default();
+            predicate_mentions.visit_where_predicate(predicate);
+            generics
+                .params
+                .iter()
+                .filter(|param| predicate_mentions.contains(param))
+                .all(|param| mentions.contains(param))
+        })
+        .cloned()
+        .collect();
+    if !predicates.is_empty() {
+        used.make_where_clause().predicates.extend(predicates);
+    }
+    (used, unused)
+}
+
+/// Predicates for the `Display` and `Debug` impls.
+///
+/// These are the enum's own where clause, plus bounds for generic fields that are used in
+/// format strings.
+pub fn impl_bounds(e: &ErrorEnum<'_>) -> Vec<TokenStream2> {
+    let ErrorEnum {
+        generics, variants, ..
+    } = e;
+
+    let mut bounds = where_predicates(generics);
+    for v in variants {
+        let texts = v.error_text.iter().chain(&v.help_text).chain(&v.note_text);
+        for (field, fmt_trait) in texts.flat_map(|text| &text.uses) {
+            if let Some(ty) = v.field_ty(field) {
+                if needs_bound(e, ty) {
+                    bounds.push(quote! { #ty: #fmt_trait });
+                }
+            }
+        }
+    }
+
+    dedup(&mut bounds);
+    bounds
+}
+
+/// Predicates for a structural `Debug` impl, which requires every generic field to be `Debug`.
+pub fn debug_bounds(e: &ErrorEnum<'_>) -> Vec<TokenStream2> {
+    let mut bounds = where_predicates(e.generics);
+    for v in &e.variants {
+        for ty in &v.all_field_tys {
+            if needs_bound(e, ty) {
+                bounds.push(quote! { #ty: ::core::fmt::Debug });
+            }
+        }
+    }
+    dedup(&mut bounds);
+    bounds
+}
+
+/// Predicates for the `Display` impl, and the `Debug` impl that forwards to it.
+///
+/// Printing the chain goes through the `Diagnostic` impl, and so needs what it needs.
+pub fn display_bounds(e: &ErrorEnum<'_>) -> Vec<TokenStream2> {
+    if e.display_chain {
+        diagnostic_bounds(e)
+    } else {
+        impl_bounds(e)
+    }
+}
+
+/// Predicates for the `Error` impl, which also needs whatever the `Debug` impl needs, and generic
+/// sources to be errors.
+pub fn error_bounds(e: &ErrorEnum<'_>) -> Vec<TokenStream2> {
+    let mut bounds = impl_bounds(e);
+    for source in e.variants.iter().filter_map(|v| v.source.as_ref()) {
+        let ty = source.ty;
+        if needs_bound(e, ty) {
+            bounds.push(quote! { #ty: ::core::error::Error + 'static });
+        }
+    }
+    match e.debug {
+        // Which is `display_bounds`, without going around in circles
+        DebugStyle::Display if e.display_chain => bounds.extend(serialize_bounds(e)),
+        DebugStyle::Display => {}
+        DebugStyle::Structural => bounds.extend(debug_bounds(e)),
+        // Assume the bounds of `#[derive(Debug)]`
+        DebugStyle::None => bounds.extend(
+            e.generics
+                .type_params()
+                .map(|param| &param.ident)
+                .map(|param| quote! { #param: ::core::fmt::Debug }),
+        ),
+    }
+    dedup(&mut bounds);
+    bounds
+}
+
+/// Predicates for the `Diagnostic` impl, which also serializes generic `#[serialize]` fields.
+pub fn diagnostic_bounds(e: &ErrorEnum<'_>) -> Vec<TokenStream2> {
+    let mut bounds = error_bounds(e);
+    bounds.extend(serialize_bounds(e));
+    dedup(&mut bounds);
+    bounds
+}
+
+fn serialize_bounds(e: &ErrorEnum<'_>) -> Vec<TokenStream2> {
+    if !cfg!(feature = "serde") {
+        return Vec::new();
+    }
+    e.variants
+        .iter()
+        .flat_map(|v| &v.serialize)
+        .map(|field| field.ty)
+        .filter(|ty| needs_bound(e, ty))
+        .map(|ty| quote! { #ty: ::error::__private::json::Serialize })
+        .collect()
+}
+
+fn where_predicates(generics: &Generics) -> Vec<TokenStream2> {
+    generics
+        .where_clause
+        .iter()
+        .flat_map(|where_clause| &where_clause.predicates)
+        .map(|predicate| quote! { #predicate })
+        .collect()
+}
+
+fn dedup(bounds: &mut Vec<TokenStream2>) {
+    let mut seen = HashSet::new();
+    bounds.retain(|bound| seen.insert(bound.to_string()));
+}
@@ -1,14 +1,39 @@
 use proc_macro::TokenStream;
 use proc_macro2::TokenStream as TokenStream2;
-use quote::{quote, quote_spanned};
+use quote::{quote, quote_spanned, ToTokens};
+use syn::spanned::Spanned;
 
 mod variant;
 use variant::Sub;
 
+mod debug;
 mod display;
 mod error_impl;
+mod format;
+mod generics;
+mod registry;
 
-#[proc_macro_derive(Error, attributes(note, help, error, source, top_level, location))]
+#[proc_macro_derive(
+    Error,
+    attributes(
+        note,
+        help,
+        error,
+        source,
+        from,
+        top_level,
+        display_chain,
+        debug,
+        location,
+        backtrace,
+        trace,
+        serialize,
+        code,
+        code_prefix,
+        clone,
+        from_none
+    )
+)]
 pub fn derive_error(input: TokenStream) -> TokenStream {
     let input = syn::parse_macro_input!(input as syn::DeriveInput);
 
@@ -17,7 +42,13 @@ pub fn derive_error(input: TokenStream) -> TokenStream {
 
 pub(crate) struct ErrorEnum<'tk> {
     pub enum_name: &'tk proc_macro2::Ident,
+    pub generics: &'tk syn::Generics,
+    /// Whether this is a struct, which has no variants of its own
+    pub is_struct: bool,
     pub is_top_level: bool,
+    /// Whether `Display` also prints the chain of sources, rather than only its own message
+    pub display_chain: bool,
+    pub debug: debug::DebugStyle,
     pub variants: Vec<Sub<'tk>>,
 }
 
@@ -30,75 +61,128 @@ fn error_impl(input: syn::DeriveInput) -> TokenStream2 {
             Vec::new()
         }
     };
+    let debug = match debug::parse_style(&input.attrs) {
+        Ok(x) => x,
+        Err(e) => {
+            match &mut encountered_error {
+                Some(error) => error.combine(e),
+                None => encountered_error = Some(e),
+            }
+            debug::DebugStyle::Display
+        }
+    };
 
+    let has_attr = |name| input.attrs.iter().any(|attr| attr.path().is_ident(name));
+    let is_top_level = has_attr("top_level");
     let e = ErrorEnum {
         enum_name: &input.ident,
-        is_top_level: input
-            .attrs
-            .iter()
-            .any(|attr| attr.path().is_ident("top_level")),
+        generics: &input.generics,
+        is_struct: matches!(input.data, syn::Data::Struct(_)),
+        is_top_level,
+        display_chain: is_top_level || has_attr("display_chain"),
+        debug,
         variants,
     };
 
-    let selectors: Vec<_> = e.variants.iter().map(make).collect();
+    let selectors: Vec<_> = e.variants.iter().map(|v| make(&e, v)).collect();
     let error = error_impl::make_impl(&e);
     let display_impl = display::make_impl(&e);
+    let debug_impl = debug::make_impl(&e);
+    let registry = if cfg!(feature = "registry") {
+        registry::make(&e)
+    } else {
+        quote! {}
+    };
 
     let encountered_error = encountered_error.map(syn::Error::into_compile_error);
     let ret = quote! {
         #encountered_error
         #display_impl
+        #debug_impl
         #error
+        #registry
         #(#selectors)*
     };
     ret
 }
 
-fn make(v: &Sub<'_>) -> TokenStream2 {
+fn make(e: &ErrorEnum<'_>, v: &Sub<'_>) -> TokenStream2 {
     let Sub {
         enum_name,
         name,
+        is_tuple,
         source,
         selector_fields,
+        selector_members,
         selector_field_names,
-        all_field_names,
         location,
+        backtrace,
+        trace,
+        from,
+        from_none,
+        clone,
         ..
     } = v;
 
-    let constructor = match (source, location) {
-        (Some(_), _) => {
-            quote! {}
-        }
-        (None, Some(_)) => {
-            quote! {
-                impl #name {
-                    #[track_caller]
-                    pub fn new( #(#selector_fields),* ) -> #enum_name {
-                        let location = ::core::panic::Location::caller();
-                        #enum_name::#name { #(#all_field_names),* }
-                    }
-                }
-            }
+    let (impl_generics, ty_generics, where_clause) = e.generics.split_for_impl();
+    let enum_ty = quote! { #enum_name #ty_generics };
+
+    // The selector only gets the generic parameters that its fields use.
+    let (selector_generics, fn_generics) =
+        generics::split_used(e.generics, selector_fields.iter().map(|field| &field.ty));
+    let (selector_impl_generics, selector_ty_generics, selector_where_clause) =
+        selector_generics.split_for_impl();
+
+    let selector_tys = selector_fields.iter().map(|field| &field.ty);
+    let constructed = v.pat();
+
+    // What is captured when the error is made, with the location expression `at`.
+    let captures = |at: TokenStream2| {
+        let location = location.as_ref().map(|location| {
+            let location = &location.name;
+            quote! { let #location = #at; }
+        });
+        let backtrace = backtrace.as_ref().map(|backtrace| {
+            let backtrace = &backtrace.name;
+            quote! { let #backtrace = ::error::__private::Backtrace::capture(); }
+        });
+        let trace = trace.as_ref().map(|trace| {
+            let trace = &trace.name;
+            quote! { let #trace = ::error::Trace::from(#at); }
+        });
+        quote! {
+            #location
+            #backtrace
+            #trace
         }
-        (None, None) => {
-            quote! {
-                impl #name {
-                    #[track_caller]
-                    pub fn new( #(#selector_fields),* ) -> #enum_name {
-                        #enum_name::#name { #(#all_field_names),* }
-                    }
+    };
+    let location_get = captures(quote! { ::core::panic::Location::caller() });
+    let bind_captures = captures(quote! { __location });
+
+    let constructor = if source.is_some() {
+        quote! {}
+    } else {
+        let fn_generics = &fn_generics.params;
+        quote! {
+            impl #selector_impl_generics #name #selector_ty_generics #selector_where_clause {
+                #[track_caller]
+                pub fn new < #fn_generics > ( #(#selector_field_names: #selector_tys),* ) -> #enum_ty #where_clause {
+                    #location_get
+                    #constructed
                 }
             }
         }
     };
 
     let span = name.span();
+    // Selectors without fields are always `Clone`, for `StreamContext::context`
     let subs = if selector_fields.is_empty() {
         quote_spanned! {span=>
+            #[derive(Clone, Copy)]
             pub struct #name;
         }
     } else {
+        let derive = clone.then(|| quote! { #[derive(Clone)] });
         let fields: Vec<_> = selector_fields
             .iter()
             .map(
@@ -109,6 +193,10 @@ fn make(v: &Sub<'_>) -> TokenStream2 {
                      ty,
                      ..
                  }| {
+                    // Our own attributes mean nothing on the selector
+                    let attrs = attrs
+                        .iter()
+                        .filter(|attr| !attr.path().is_ident("serialize"));
                     quote! {
                         #(#attrs)*
                         pub #ident #colon_token #ty,
@@ -116,74 +204,208 @@ fn make(v: &Sub<'_>) -> TokenStream2 {
                 },
             )
             .collect();
-        quote_spanned! {span=>
-            pub struct #name {
-                #(#fields)*
+        if *is_tuple {
+            quote_spanned! {span=>
+                #derive
+                pub struct #name #selector_generics (
+                    #(#fields)*
+                ) #selector_where_clause;
+            }
+        } else {
+            quote_spanned! {span=>
+                #derive
+                pub struct #name #selector_generics #selector_where_clause {
+                    #(#fields)*
+                }
             }
         }
     };
 
+    let selector_pat = selector_members
+        .iter()
+        .zip(selector_field_names)
+        .map(|(member, name)| variant::field_pat(member, name));
     let deconstructor = quote! {
-        let #name {#(#selector_field_names),*}
+        let #name { #(#selector_pat),* }
     };
-    let location_get = if location.is_some() {
+
+    // `bind` is `bind_at` the caller.
+    let with_impl = |impl_generics: TokenStream2, residual: TokenStream2, body: TokenStream2| {
         quote! {
-            let location = ::core::panic::Location::caller();
+            impl #impl_generics ::error::With<#residual, #enum_ty> for #name #selector_ty_generics #where_clause {
+                fn bind(self, residual: #residual) -> #enum_ty {
+                    ::error::With::bind_at(self, residual, ::core::panic::Location::caller())
+                }
+
+                #[allow(unused_variables)]
+                fn bind_at(self, residual: #residual, __location: &'static ::core::panic::Location<'static>) -> #enum_ty {
+                    #body
+                    #bind_captures
+                    #deconstructor = self;
+                    #constructed
+                }
+            }
         }
-    } else {
-        quote! {}
     };
+    let impl_generics = impl_generics.to_token_stream();
+    let option = quote! { ::core::option::Option<::core::convert::Infallible> };
 
     let impls = if let Some(source) = source {
+        let source_name = &source.name;
+        let source = source.ty;
+        let option_impl = from_none.then(|| {
+            with_impl(
+                impl_generics.clone(),
+                option.clone(),
+                quote! { let #source_name = <#source as ::core::default::Default>::default(); },
+            )
+        });
+        let result_impl = with_impl(
+            impl_generics.clone(),
+            quote! { ::core::result::Result<::core::convert::Infallible, #source> },
+            quote! {
+                let #source_name = match residual {
+                    Ok(f) => match f {},
+                    Err(e) => e,
+                };
+            },
+        );
         quote! {
-            impl ::error::With<::core::result::Result<::core::convert::Infallible, #source>, #enum_name> for #name {
-                fn bind(self, source: ::core::result::Result<::core::convert::Infallible, #source>) -> #enum_name {
-                    let source = match source {
-                        Ok(f) => match f {},
-                        Err(e) => e,
-                    };
-                    #location_get
-                    #deconstructor = self;
-                    #enum_name::#name { #(#all_field_names),* }
-                }
-            }
+            #option_impl
+            #result_impl
         }
     } else {
+        let mut with_generics = e.generics.clone();
+        with_generics
+            .params
+            .push(syn::parse_quote!(__E: ::core::error::Error));
+        let (with_impl_generics, _, _) = with_generics.split_for_impl();
+        let result_impl = with_impl(
+            with_impl_generics.to_token_stream(),
+            quote! { ::core::result::Result<::core::convert::Infallible, __E> },
+            quote! {},
+        );
+        let option_impl = with_impl(impl_generics.clone(), option, quote! {});
         quote! {
-            impl <E: ::core::error::Error> ::error::With<::core::result::Result<::core::convert::Infallible, E>, #enum_name> for #name {
-                fn bind(self, _: ::core::result::Result<::core::convert::Infallible, E>) -> #enum_name {
-                    #location_get
-                    #deconstructor = self;
-                    #enum_name::#name { #(#all_field_names),* }
-                }
-            }
+            #result_impl
+            #option_impl
+        }
+    };
 
-            impl ::error::With<::core::option::Option<::core::convert::Infallible>, #enum_name> for #name {
-                fn bind(self, _: ::core::option::Option<::core::convert::Infallible>) -> #enum_name {
-                    #location_get
-                    #deconstructor = self;
-                    #enum_name::#name { #(#all_field_names),* }
+    let from_impl = match source {
+        Some(source) if *from => {
+            let source_name = &source.name;
+            let source = source.ty;
+            quote! {
+                impl #impl_generics ::core::convert::From<#source> for #enum_ty #where_clause {
+                    #[track_caller]
+                    fn from(#source_name: #source) -> Self {
+                        #location_get
+                        #constructed
+                    }
                 }
             }
         }
+        _ => quote! {},
     };
 
+    let unused = make_unused_warnings(v);
+
     quote! {
         #subs
         #impls
         #constructor
+        #from_impl
+        #unused
     }
 }
 
+/// Warns about selector fields that no text uses, through a deprecated constant because proc
+/// macros can not warn on stable.
+fn make_unused_warnings(v: &Sub<'_>) -> TokenStream2 {
+    let used: Vec<_> = v
+        .error_text
+        .iter()
+        .chain(&v.help_text)
+        .chain(&v.note_text)
+        .flat_map(|text| &text.idents)
+        .collect();
+    let warnings = v
+        .selector_fields
+        .iter()
+        .zip(&v.selector_field_names)
+        .filter(|(field, name)| {
+            let silenced = field
+                .ident
+                .as_ref()
+                .is_some_and(|ident| ident.to_string().starts_with('_'));
+            // Without the `serde` feature, `#[serialize]` does not make it show up anywhere
+            let serialized = cfg!(feature = "serde")
+                && field
+                    .attrs
+                    .iter()
+                    .any(|attr| attr.path().is_ident("serialize"));
+            !silenced && !serialized && !used.contains(name)
+        })
+        .map(|(field, _)| {
+            // Tuple fields have no name to start with `_`
+            let (span, note) = match &field.ident {
+                Some(ident) => (ident.span(), errs::UNUSED_FIELD),
+                None => (field.ty.span(), errs::UNUSED_TUPLE_FIELD),
+            };
+            quote_spanned! {span=>
+                const _: () = {
+                    #[deprecated(note = #note)]
+                    #[allow(non_upper_case_globals)]
+                    const field_never_displayed: () = ();
+                    field_never_displayed
+                };
+            }
+        });
+    quote! { #(#warnings)* }
+}
+
 mod errs {
-    pub const ONLY_ENUM: &str = "only enum errors are supported";
-    pub const ONLY_NAMED_FIELDS: &str = "only enums with named fields are supported";
+    pub const NO_UNION: &str = "unions are not supported";
     pub const DUPE_SOURCE: &str = "more than one `#[source]` attribute";
     pub const DUPE_LOCATION: &str = "more than one `#[location]` attribute";
+    pub const DUPE_BACKTRACE: &str = "more than one `#[backtrace]` attribute";
+    pub const BACKTRACE_STD: &str = "`#[backtrace]` requires the `std` feature of `error`";
+    pub const DUPE_TRACE: &str = "more than one `#[trace]` attribute";
+    pub const TRACE_ALLOC: &str = "`#[trace]` requires the `alloc` feature of `error`";
+    pub const DUPE_CODE_ATTR: &str = "more than one `#[code]` attribute";
+    pub const DUPE_CODE_PREFIX: &str = "more than one `#[code_prefix]` attribute";
+    pub const DUPE_CODE: &str = "this error code is already used by another variant";
+    pub const MISSING_CODE: &str =
+        "missing `#[code = \"...\"]`, which every variant that is not transparent needs when any variant has one";
+    pub const DUPE_FROM: &str = "more than one `#[from]` for this type";
+    pub const FROM_FIELDS: &str =
+        "`#[from]` is only supported without fields other than the source and location";
     pub const NO_INNER: &str = "inner attributes are not supported in this position";
     pub const NEED_ERROR_TEXT: &str = "at least one `#[error = \"msg\"]` attribute is required";
-    pub const MUST_BE_NAMED_SOURCE: &str = "field of #[source] must be named `source`";
-    pub const MUST_BE_NAMED_LOCATION: &str = "field of #[location] must be named `location`";
-    pub const NO_FORMAT_ARG: &str =
-        "positional argument in format string, but no arguments were given";
+    pub const EXPECTED_TRANSPARENT: &str = "expected a format string or `transparent`";
+    pub const EXPECTED_DEBUG_STYLE: &str = "expected `structural` or `none`";
+    pub const EXPECTED_NONE: &str = "expected a format string or `none`";
+    pub const NONE_WITH_TEXT: &str =
+        "`none` cannot be combined with another `#[help]` or `#[note]` on the same variant";
+    pub const TRANSPARENT_TEXT: &str =
+        "`#[error(transparent)]` cannot be combined with `#[error = \"msg\"]`";
+    pub const TRANSPARENT_FIELDS: &str = "`#[error(transparent)]` requires exactly one field";
+    pub const UNCLOSED_BRACE: &str = "unclosed `{` in format string, use `{{` for a literal brace";
+    pub const UNMATCHED_BRACE: &str =
+        "unmatched `}` in format string, use `}}` for a literal brace";
+    pub const BAD_FORMAT_SPEC: &str = "invalid format spec";
+    pub const STAR_PRECISION: &str = "`.*` is not supported, use `.{precision}` instead";
+    pub const EXPECTED_PRECISION: &str = "expected a precision after `.`";
+    pub const EMPTY_COUNT: &str = "expected a field or expression in the braces";
+    pub const UNUSED_FIELD: &str =
+        "this field is not used by any `#[error]`, `#[help]` or `#[note]`, start its name with `_` to allow that";
+    pub const UNUSED_TUPLE_FIELD: &str =
+        "this field is not used by any `#[error]`, `#[help]` or `#[note]`, use it by its index like `{0}`, or name the fields and start this one's name with `_` to allow that";
+    pub const UNKNOWN_FIELD: &str = "this variant has no field";
+    pub const BAD_FORMAT_ARG: &str = "expected a field, a tuple index or an expression";
+    pub const DUPE_FORMAT_ARG: &str = "duplicate argument with this name";
+    pub const POSITIONAL_AFTER_NAMED: &str = "positional arguments cannot follow named arguments";
+    pub const NO_POSITIONAL_ARG: &str = "there is no positional argument with this index";
+    pub const UNUSED_FORMAT_ARG: &str = "this argument is never used";
 }
@@ -0,0 +1,94 @@
+use crate::variant::Sub;
+use crate::ErrorEnum;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use quote::ToTokens;
+
+/// Registers the type and its variants in `error::registry`.
+pub(crate) fn make(e: &ErrorEnum<'_>) -> TokenStream2 {
+    let name = e.enum_name.to_string();
+    let variants = e.variants.iter().map(|v| make_variant(e, v));
+    quote! {
+        const _: () = {
+            #[::error::__private::linkme::distributed_slice(::error::__private::REGISTRY)]
+            #[linkme(crate = ::error::__private::linkme)]
+            static ERROR_TYPE: ::error::registry::ErrorType = ::error::registry::ErrorType {
+                name: #name,
+                module: ::core::module_path!(),
+                variants: &[#(#variants),*],
+            };
+        };
+    }
+}
+
+fn make_variant(e: &ErrorEnum<'_>, v: &Sub<'_>) -> TokenStream2 {
+    let Sub {
+        ident,
+        code,
+        transparent,
+        error_text,
+        help_text,
+        note_text,
+        all_field_members,
+        all_field_tys,
+        ..
+    } = v;
+
+    let name = if e.is_struct {
+        quote! { ::core::option::Option::None }
+    } else {
+        let ident = ident.to_string();
+        quote! { ::core::option::Option::Some(#ident) }
+    };
+    let code = match code {
+        Some(code) => quote! { ::core::option::Option::Some(#code) },
+        None => quote! { ::core::option::Option::None },
+    };
+    let message = if *transparent {
+        quote! { ::core::option::Option::None }
+    } else {
+        let message: String = error_text.iter().map(|text| &*text.template).collect();
+        quote! { ::core::option::Option::Some(#message) }
+    };
+    let help = help_text.iter().map(|text| &text.template);
+    let notes = note_text.iter().map(|text| &text.template);
+    let fields = all_field_members
+        .iter()
+        .zip(all_field_tys)
+        .map(|(member, ty)| {
+            let name = match member {
+                syn::Member::Named(ident) => ident.to_string(),
+                syn::Member::Unnamed(index) => index.index.to_string(),
+            };
+            let ty = type_name(ty);
+            quote! { ::error::registry::Field { name: #name, ty: #ty } }
+        });
+
+    quote! {
+        ::error::registry::Variant {
+            name: #name,
+            code: #code,
+            message: #message,
+            help: &[#(#help),*],
+            notes: &[#(#notes),*],
+            fields: &[#(#fields),*],
+        }
+    }
+}
+
+/// `ty` as it would be written, rather than with a space between every token.
+fn type_name(ty: &syn::Type) -> String {
+    let mut name = ty.to_token_stream().to_string();
+    for (from, to) in [
+        (" :: ", "::"),
+        (":: ", "::"),
+        (" <", "<"),
+        ("< ", "<"),
+        (" >", ">"),
+    ] {
+        name = name.replace(from, to);
+    }
+    name.replace(" ,", ",")
+        .replace(" ;", ";")
+        .replace("& ", "&")
+}
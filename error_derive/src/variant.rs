@@ -1,3 +1,4 @@
+use crate::format::fmt_parse;
 use proc_macro2::Ident;
 use proc_macro2::TokenStream as TokenStream2;
 use quote::ToTokens;
@@ -6,219 +7,826 @@ use syn::parse::ParseStream;
 use syn::punctuated::Pair;
 use syn::spanned::Spanned;
 use syn::Field;
+use syn::Member;
 use syn::Type;
 
 pub struct Sub<'tk> {
     pub enum_name: &'tk Ident,
-    pub name: &'tk Ident,
-    pub source: Option<&'tk Type>,
-    pub location: Option<&'tk Type>,
+    /// Name of the variant, or of the struct
+    pub ident: &'tk Ident,
+    /// Name of the generated selector
+    pub name: Ident,
+    /// Path used to construct and match on this variant
+    pub path: TokenStream2,
+    /// Whether the fields are unnamed, in which case the selector is a tuple struct
+    pub is_tuple: bool,
+    pub source: Option<Binding<'tk>>,
+    pub location: Option<Binding<'tk>>,
+    pub backtrace: Option<Binding<'tk>>,
+    pub trace: Option<Binding<'tk>>,
+    /// Fields marked `#[serialize]`, which are included in structured output
+    pub serialize: Vec<Binding<'tk>>,
+    /// From `#[code = "..."]`, with the enum's `#[code_prefix]` already applied
+    pub code: Option<syn::LitStr>,
+    /// Whether the source is marked `#[from]`
+    pub from: bool,
+    /// Whether the source is marked `#[from_none]`, so that it is made with `Default` when the
+    /// selector is used on a `None`
+    pub from_none: bool,
+    /// Whether this is `#[error(transparent)]`, forwarding everything to its only field
+    pub transparent: bool,
+    /// Whether the selector derives `Clone` even though it has fields, from `#[clone]`
+    pub clone: bool,
     pub selector_fields: Vec<&'tk Field>,
-    pub selector_field_names: Vec<&'tk Ident>,
-    pub all_field_names: Vec<&'tk Ident>,
+    /// Members of the selector, which differ from the variant's for tuple variants
+    pub selector_members: Vec<Member>,
+    pub selector_field_names: Vec<Ident>,
+    pub all_field_members: Vec<Member>,
+    pub all_field_names: Vec<Ident>,
+    pub all_field_tys: Vec<&'tk Type>,
     pub error_text: Vec<Text>,
     pub help_text: Vec<Text>,
+    pub note_text: Vec<Text>,
 }
 
-#[derive(Debug)]
+impl<'tk> Sub<'tk> {
+    /// The type of the field bound to `name`.
+    pub fn field_ty(&self, name: &Ident) -> Option<&'tk Type> {
+        self.all_field_names
+            .iter()
+            .position(|field| field == name)
+            .map(|i| self.all_field_tys[i])
+    }
+
+    /// `Enum::Variant` or `Struct`, naming where help and notes come from.
+    pub fn origin(&self) -> String {
+        self.path.to_string().replace(' ', "")
+    }
+
+    /// Pattern (or constructor) binding every field of the variant to its name.
+    pub fn pat(&self) -> TokenStream2 {
+        let Sub {
+            path,
+            all_field_members,
+            all_field_names,
+            ..
+        } = self;
+        let fields = all_field_members
+            .iter()
+            .zip(all_field_names)
+            .map(|(member, name)| field_pat(member, name));
+        quote::quote! { #path { #(#fields),* } }
+    }
+}
+
+/// `member: name`, or the shorthand `name` if they are the same.
+pub fn field_pat(member: &Member, name: &Ident) -> TokenStream2 {
+    match member {
+        Member::Named(ident) if ident == name => quote::quote! { #name },
+        _ => quote::quote! { #member: #name },
+    }
+}
+
+/// A field of the variant, and the name it is bound to in generated code.
+pub struct Binding<'tk> {
+    pub member: Member,
+    pub name: Ident,
+    pub ty: &'tk Type,
+}
+
+#[derive(Clone, Debug)]
 pub struct Text {
+    /// The format string as written
+    pub template: String,
     pub lit: proc_macro2::Literal,
     pub args: Vec<TokenStream2>,
+    /// Fields that are directly used as arguments, with the formatting trait they are used through
+    pub uses: Vec<(Ident, TokenStream2)>,
+    /// Names that have to be fields: arguments, widths and precisions, and what expressions start
+    /// with
+    pub fields: Vec<Ident>,
+    /// Every identifier in the arguments, which might be a field
+    pub idents: Vec<Ident>,
+    /// Arguments given after the format string, bound to these names before formatting
+    pub bindings: Vec<(Ident, syn::Expr)>,
+}
+
+impl Text {
+    /// Writes the text to `f`.
+    pub fn write(&self) -> TokenStream2 {
+        let Text {
+            lit,
+            args,
+            bindings,
+            ..
+        } = self;
+        if bindings.is_empty() {
+            return quote::quote! { ::core::write!(f, #lit, #(#args),*) };
+        }
+        // In one `let`, so that no argument sees another that shadows a field
+        let (names, exprs): (Vec<_>, Vec<_>) = bindings.iter().cloned().unzip();
+        quote::quote! {{
+            let (#(#names,)*) = (#(&(#exprs),)*);
+            ::core::write!(f, #lit, #(#args),*)
+        }}
+    }
+}
+
+/// The arguments after the format string in `#[error("...", a, b = c)]`.
+struct ExplicitArgs {
+    positional: Vec<syn::Expr>,
+    named: Vec<(Ident, syn::Expr)>,
+}
+
+impl Parse for ExplicitArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut args = ExplicitArgs {
+            positional: Vec::new(),
+            named: Vec::new(),
+        };
+        while !input.is_empty() {
+            input.parse::<syn::Token![,]>()?;
+            if input.is_empty() {
+                break;
+            }
+            if input.peek(syn::Ident)
+                && input.peek2(syn::Token![=])
+                && !input.peek2(syn::Token![==])
+            {
+                let name: Ident = input.parse()?;
+                input.parse::<syn::Token![=]>()?;
+                if args.named.iter().any(|(other, _)| *other == name) {
+                    return Err(syn::Error::new(name.span(), crate::errs::DUPE_FORMAT_ARG));
+                }
+                args.named.push((name, input.parse()?));
+            } else {
+                let expr: syn::Expr = input.parse()?;
+                if !args.named.is_empty() {
+                    return Err(syn::Error::new(
+                        expr.span(),
+                        crate::errs::POSITIONAL_AFTER_NAMED,
+                    ));
+                }
+                args.positional.push(expr);
+            }
+        }
+        Ok(args)
+    }
 }
 
 impl Parse for Text {
     fn parse(input: ParseStream) -> syn::Result<Self> {
         let literal: proc_macro2::Literal = input.fork().parse()?;
         let lit: syn::LitStr = input.parse()?;
+        let explicit: ExplicitArgs = input.parse()?;
         let s = lit.value();
 
-        let sl = s.as_str();
-        let (out, args) = fmt_parse(sl).expect("cannot parse format");
-        Ok(Text {
+        let (out, args) = fmt_parse(&s).map_err(|e| {
+            let span = literal
+                .subspan((e.range.start + 1)..(e.range.end + 1))
+                .unwrap_or(literal.span());
+            syn::Error::new(span, e.message)
+        })?;
+        let specs = placeholder_specs(&out);
+
+        let mut text = Text {
+            template: s.clone(),
             lit: proc_macro2::Literal::string(&out),
-            args: args
-                .into_iter()
-                .map(|(x, offset)| {
-                    let span = literal
-                        .subspan((offset + 1)..(offset + x.len() + 1))
-                        .unwrap_or(literal.span());
-                    if let Ok(mut expr) = syn::parse_str::<syn::Ident>(&x) {
-                        expr.set_span(span);
-                        Ok(quote::quote! { #expr })
-                    } else if x == "" {
-                        let span = literal
-                            .subspan((offset)..(offset + 2))
-                            .unwrap_or(literal.span());
-                        Err(syn::Error::new(span, crate::errs::NO_FORMAT_ARG))
-                    } else {
-                        let expr = syn::parse_str::<syn::Expr>(&x)
-                            .expect(&format!("cannot parse {x} as expr"));
-                        Ok(quote::quote_spanned! {span=>
-                            #expr
-                        })
+            args: Vec::new(),
+            uses: Vec::new(),
+            fields: Vec::new(),
+            idents: Vec::new(),
+            bindings: Vec::new(),
+        };
+        let positional_names: Vec<Ident> = (0..explicit.positional.len())
+            .map(|i| quote::format_ident!("__arg{}", i))
+            .collect();
+        let mut used_positional = vec![false; explicit.positional.len()];
+        let mut used_named = vec![false; explicit.named.len()];
+        // `{}` is the next positional argument, or the next tuple field if there are none
+        let mut next = 0usize;
+        for (i, (x, offset)) in args.into_iter().enumerate() {
+            let span = literal
+                .subspan((offset + 1)..(offset + x.len() + 1))
+                .unwrap_or(literal.span());
+            let spec = specs.get(i);
+            let index = if x.is_empty() && spec.is_some() {
+                next += 1;
+                Some(next - 1)
+            } else if !x.is_empty() && x.bytes().all(|b| b.is_ascii_digit()) {
+                x.parse().ok()
+            } else {
+                None
+            };
+            let ident = syn::parse_str::<syn::Ident>(&x).ok();
+
+            // An explicit argument, and the expression it is bound to
+            let explicit_arg = match (index, &ident) {
+                (Some(index), _) if !explicit.positional.is_empty() => {
+                    let Some(expr) = explicit.positional.get(index) else {
+                        return Err(syn::Error::new(span, crate::errs::NO_POSITIONAL_ARG));
+                    };
+                    used_positional[index] = true;
+                    Some((positional_names[index].clone(), expr))
+                }
+                (None, Some(ident)) => explicit
+                    .named
+                    .iter()
+                    .position(|(name, _)| name == ident)
+                    .map(|j| {
+                        used_named[j] = true;
+                        (explicit.named[j].0.clone(), &explicit.named[j].1)
+                    }),
+                _ => None,
+            };
+            let field = match (&explicit_arg, index, ident) {
+                (Some(_), _, _) => None,
+                // Positional arguments refer to the fields of a tuple variant
+                (None, Some(index), _) => Some(quote::format_ident!("_{}", index, span = span)),
+                (None, None, Some(mut ident)) => {
+                    ident.set_span(span);
+                    Some(ident)
+                }
+                (None, None, None) => None,
+            };
+
+            if let Some((name, expr)) = explicit_arg {
+                let name = Ident::new(&name.to_string(), span);
+                match spec {
+                    Some(spec) => {
+                        text.args.push(quote::quote! { #name });
+                        // A field given as is still needs its type to implement the trait
+                        if let (syn::Expr::Path(_), Some(field)) = (expr, expr_root(expr)) {
+                            text.uses.push((field, fmt_trait(spec)));
+                        }
                     }
-                })
-                .collect::<Result<_, _>>()?,
-        })
+                    None => text.args.push(quote::quote! { *#name }),
+                }
+            } else if let Some(field) = field {
+                text.fields.push(field.clone());
+                text.idents.push(field.clone());
+                match spec {
+                    Some(spec) => {
+                        text.args.push(quote::quote! { #field });
+                        text.uses.push((field, fmt_trait(spec)));
+                    }
+                    // Widths and precisions have to be a `usize`, not a reference to one
+                    None => text.args.push(quote::quote! { *#field }),
+                }
+            } else {
+                let expr = syn::parse_str::<syn::Expr>(&x)
+                    .map_err(|_| syn::Error::new(span, crate::errs::BAD_FORMAT_ARG))?;
+                if let Some(mut root) = expr_root(&expr) {
+                    root.set_span(span);
+                    text.fields.push(root);
+                }
+                idents(expr.to_token_stream(), &mut text.idents);
+                text.args.push(quote::quote_spanned! {span=>
+                    #expr
+                });
+            }
+        }
+
+        let explicit_args = positional_names
+            .into_iter()
+            .zip(explicit.positional)
+            .zip(used_positional)
+            .chain(explicit.named.into_iter().zip(used_named));
+        for ((name, expr), used) in explicit_args {
+            if !used {
+                return Err(syn::Error::new(expr.span(), crate::errs::UNUSED_FORMAT_ARG));
+            }
+            if let Some(root) = expr_root(&expr) {
+                text.fields.push(root);
+            }
+            idents(expr.to_token_stream(), &mut text.idents);
+            text.bindings.push((name, expr));
+        }
+        Ok(text)
     }
 }
 
-pub fn parse(input: &syn::DeriveInput) -> syn::Result<Vec<Sub<'_>>> {
-    let syn::Data::Enum(data) = &input.data else {
-        return Err(syn::Error::new(input.span(), crate::errs::ONLY_ENUM));
+/// The variable an expression like `a.b.len()` starts with, if it is one rather than a constant.
+fn expr_root(expr: &syn::Expr) -> Option<Ident> {
+    match expr {
+        syn::Expr::Field(expr) => expr_root(&expr.base),
+        syn::Expr::MethodCall(expr) => expr_root(&expr.receiver),
+        syn::Expr::Index(expr) => expr_root(&expr.expr),
+        syn::Expr::Reference(expr) => expr_root(&expr.expr),
+        syn::Expr::Unary(syn::ExprUnary {
+            op: syn::UnOp::Deref(_),
+            expr,
+            ..
+        }) => expr_root(expr),
+        syn::Expr::Paren(expr) => expr_root(&expr.expr),
+        syn::Expr::Path(expr) if expr.qself.is_none() => {
+            let ident = expr.path.get_ident()?;
+            let is_variable = ident != "self"
+                && ident
+                    .to_string()
+                    .starts_with(|c: char| c.is_lowercase() || c == '_');
+            is_variable.then(|| ident.clone())
+        }
+        _ => None,
+    }
+}
+
+fn idents(tokens: TokenStream2, out: &mut Vec<Ident>) {
+    for token in tokens {
+        match token {
+            proc_macro2::TokenTree::Ident(ident) => out.push(ident),
+            proc_macro2::TokenTree::Group(group) => idents(group.stream(), out),
+            _ => {}
+        }
+    }
+}
+
+/// The format specs (without the `:`) of every placeholder in a string produced by `fmt_parse`.
+fn placeholder_specs(s: &str) -> Vec<&str> {
+    let mut specs = Vec::new();
+    let mut rest = s;
+    while let Some(start) = rest.find('{') {
+        if rest[start + 1..].starts_with('{') {
+            rest = &rest[start + 2..];
+            continue;
+        }
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        let placeholder = &rest[start + 1..start + len];
+        specs.push(placeholder.split_once(':').map_or("", |(_, spec)| spec));
+        rest = &rest[start + len + 1..];
+    }
+    specs
+}
+
+/// The `core::fmt` trait a format spec uses.
+fn fmt_trait(spec: &str) -> TokenStream2 {
+    let name = if spec.ends_with('?') {
+        "Debug"
+    } else {
+        match spec.chars().last() {
+            Some('x') => "LowerHex",
+            Some('X') => "UpperHex",
+            Some('o') => "Octal",
+            Some('b') => "Binary",
+            Some('e') => "LowerExp",
+            Some('E') => "UpperExp",
+            Some('p') => "Pointer",
+            _ => "Display",
+        }
     };
-    let enum_name = &input.ident;
+    let name = Ident::new(name, proc_macro2::Span::call_site());
+    quote::quote! { ::core::fmt::#name }
+}
 
-    let mut out = Vec::new();
-    for Pair::Punctuated(variant, _) | Pair::End(variant) in data.variants.pairs() {
-        let mut source = None;
-        let mut location = None;
-        let name = &variant.ident;
+pub fn parse(input: &syn::DeriveInput) -> syn::Result<Vec<Sub<'_>>> {
+    let mut subs = parse_subs(input)?;
+    let prefix = parse_code_prefix(&input.attrs)?;
+    for sub in &mut subs {
+        if let Some(code) = &mut sub.code {
+            *code = syn::LitStr::new(&format!("{prefix}{}", code.value()), code.span());
+        }
+    }
+    check_codes(&subs)?;
+    Ok(subs)
+}
 
-        let mut all_fields: Vec<&Field> = Vec::new();
-        let mut selector_fields: Vec<&Field> = Vec::new();
+fn parse_subs(input: &syn::DeriveInput) -> syn::Result<Vec<Sub<'_>>> {
+    let enum_name = &input.ident;
 
-        let mut error_text = Vec::new();
-        let mut help_text = Vec::new();
-        for attr in &variant.attrs {
-            if let syn::AttrStyle::Inner(_) = attr.style {
-                return Err(syn::Error::new(attr.span(), crate::errs::NO_INNER));
+    match &input.data {
+        syn::Data::Enum(data) => {
+            let defaults = parse_defaults(&input.attrs)?;
+            let mut out = Vec::new();
+            for Pair::Punctuated(variant, _) | Pair::End(variant) in data.variants.pairs() {
+                let name = &variant.ident;
+                out.push(parse_sub(
+                    enum_name,
+                    name,
+                    name.clone(),
+                    quote::quote! { #enum_name :: #name },
+                    &variant.attrs,
+                    &variant.fields,
+                    &defaults,
+                    variant.span(),
+                )?);
             }
+            check_dupe_from(&out)?;
+            Ok(out)
+        }
+        syn::Data::Struct(data) => {
+            let name = quote::format_ident!("{}Context", enum_name);
+            let sub = parse_sub(
+                enum_name,
+                enum_name,
+                name,
+                quote::quote! { #enum_name },
+                &input.attrs,
+                &data.fields,
+                &Defaults::default(),
+                input.span(),
+            )?;
+            Ok(vec![sub])
+        }
+        syn::Data::Union(_) => Err(syn::Error::new(input.span(), crate::errs::NO_UNION)),
+    }
+}
+
+/// The `#[help]` and `#[note]` of an enum, for the variants that have none of their own.
+#[derive(Default)]
+struct Defaults {
+    help: Vec<Text>,
+    note: Vec<Text>,
+}
+
+fn parse_defaults(attrs: &[syn::Attribute]) -> syn::Result<Defaults> {
+    let mut defaults = Defaults::default();
+    for attr in attrs {
+        let texts = if attr.path().is_ident("help") {
+            &mut defaults.help
+        } else if attr.path().is_ident("note") {
+            &mut defaults.note
+        } else {
+            continue;
+        };
+        match &attr.meta {
+            syn::Meta::NameValue(meta) => texts.push(syn::parse2(meta.value.to_token_stream())?),
+            syn::Meta::List(list) => texts.push(list.parse_args()?),
+            syn::Meta::Path(_) => {}
+        }
+    }
+    Ok(defaults)
+}
+
+/// The text of a `#[help(...)]` or `#[note(...)]`, or `None` for `#[help(none)]`.
+fn parse_text_list(list: &syn::MetaList) -> syn::Result<Option<Text>> {
+    if let Ok(ident) = syn::parse2::<Ident>(list.tokens.clone()) {
+        if ident != "none" {
+            return Err(syn::Error::new(ident.span(), crate::errs::EXPECTED_NONE));
+        }
+        return Ok(None);
+    }
+    list.parse_args().map(Some)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn parse_sub<'tk>(
+    enum_name: &'tk Ident,
+    ident: &'tk Ident,
+    name: Ident,
+    path: TokenStream2,
+    attrs: &'tk [syn::Attribute],
+    fields: &'tk syn::Fields,
+    defaults: &Defaults,
+    span: proc_macro2::Span,
+) -> syn::Result<Sub<'tk>> {
+    let mut source = None;
+    let mut location = None;
+    let mut backtrace = None;
+    let mut trace = None;
 
-            if let syn::Meta::NameValue(syn::MetaNameValue { path, value, .. }) = &attr.meta {
-                // This could probably be nicer
-                let value = value.into_token_stream().into();
+    let mut selector_fields: Vec<&Field> = Vec::new();
+    let mut selector_members = Vec::new();
+    let mut selector_field_names = Vec::new();
+    let mut all_field_members = Vec::new();
+    let mut all_field_names = Vec::new();
+    let mut all_field_tys = Vec::new();
+
+    let mut error_text: Vec<Text> = Vec::new();
+    let mut help_text = Vec::new();
+    let mut note_text = Vec::new();
+    let mut no_help = None;
+    let mut no_note = None;
+    let mut transparent = None;
+    let mut code = None;
+    let mut clone = false;
+    for attr in attrs {
+        if let syn::AttrStyle::Inner(_) = attr.style {
+            return Err(syn::Error::new(attr.span(), crate::errs::NO_INNER));
+        }
+
+        match &attr.meta {
+            syn::Meta::NameValue(syn::MetaNameValue { path, value, .. }) => {
+                let value = value.into_token_stream();
 
                 if path.is_ident("error") {
-                    error_text.push(syn::parse(value)?);
+                    error_text.push(syn::parse2(value)?);
                 } else if path.is_ident("help") {
-                    help_text.push(syn::parse(value)?);
+                    help_text.push(syn::parse2(value)?);
+                } else if path.is_ident("note") {
+                    note_text.push(syn::parse2(value)?);
+                } else if path.is_ident("code") {
+                    let lit: syn::LitStr = syn::parse2(value)?;
+                    if code.replace(lit).is_some() {
+                        return Err(syn::Error::new(attr.span(), crate::errs::DUPE_CODE_ATTR));
+                    }
                 }
             }
+            syn::Meta::List(list) if list.path.is_ident("error") => {
+                if list
+                    .tokens
+                    .clone()
+                    .into_iter()
+                    .next()
+                    .is_some_and(|token| matches!(token, proc_macro2::TokenTree::Literal(_)))
+                {
+                    error_text.push(list.parse_args()?);
+                    continue;
+                }
+                let arg: Ident = list.parse_args()?;
+                if arg != "transparent" {
+                    return Err(syn::Error::new(
+                        arg.span(),
+                        crate::errs::EXPECTED_TRANSPARENT,
+                    ));
+                }
+                transparent = Some(attr);
+            }
+            syn::Meta::Path(path) if path.is_ident("clone") => clone = true,
+            syn::Meta::List(list) if list.path.is_ident("help") => match parse_text_list(list)? {
+                Some(text) => help_text.push(text),
+                None => no_help = Some(attr),
+            },
+            syn::Meta::List(list) if list.path.is_ident("note") => match parse_text_list(list)? {
+                Some(text) => note_text.push(text),
+                None => no_note = Some(attr),
+            },
+            _ => {}
+        }
+    }
+    for (texts, none, default) in [
+        (&mut help_text, no_help, &defaults.help),
+        (&mut note_text, no_note, &defaults.note),
+    ] {
+        match none {
+            Some(none) if !texts.is_empty() => {
+                return Err(syn::Error::new(none.span(), crate::errs::NONE_WITH_TEXT));
+            }
+            Some(_) => {}
+            // Transparent variants forward the help and notes of the error they wrap instead
+            None if texts.is_empty() && transparent.is_none() => texts.clone_from(default),
+            None => {}
+        }
+    }
+    if let Some(transparent) = transparent {
+        if !error_text.is_empty() {
+            return Err(syn::Error::new(
+                transparent.span(),
+                crate::errs::TRANSPARENT_TEXT,
+            ));
         }
-        if error_text.is_empty() {
+        if fields.len() != 1 {
             return Err(syn::Error::new(
-                variant.span(),
-                crate::errs::NEED_ERROR_TEXT,
+                transparent.span(),
+                crate::errs::TRANSPARENT_FIELDS,
             ));
         }
+    } else if error_text.is_empty() {
+        return Err(syn::Error::new(span, crate::errs::NEED_ERROR_TEXT));
+    }
 
-        match &variant.fields {
-            syn::Fields::Named(syn::FieldsNamed { named, .. }) => {
-                for field in named.pairs() {
-                    let field = field.value();
-
-                    if field
-                        .attrs
-                        .iter()
-                        .any(|attr| attr.path().is_ident("source"))
-                    {
-                        if field.ident.as_ref().expect("tuple enum is not allowed") != "source" {
-                            return Err(syn::Error::new(
-                                field.ident.span(),
-                                crate::errs::MUST_BE_NAMED_SOURCE,
-                            ));
-                        }
-                        if source.replace(&field.ty).is_some() {
-                            return Err(syn::Error::new(field.span(), crate::errs::DUPE_SOURCE));
-                        }
-                    } else if field
-                        .attrs
-                        .iter()
-                        .any(|attr| attr.path().is_ident("location"))
-                    {
-                        if field.ident.as_ref().expect("tuple enum is not allowed") != "location" {
-                            return Err(syn::Error::new(
-                                field.ident.span(),
-                                crate::errs::MUST_BE_NAMED_LOCATION,
-                            ));
-                        }
-                        if location.replace(&field.ty).is_some() {
-                            return Err(syn::Error::new(field.span(), crate::errs::DUPE_LOCATION));
-                        }
-                    } else {
-                        selector_fields.push(*field);
-                    }
-                    all_fields.push(*field);
-                }
+    let is_tuple = matches!(fields, syn::Fields::Unnamed(_));
+    let has_source_attr = fields.iter().any(is_source_attr);
+    let mut from = None;
+    let mut from_none = false;
+    let mut serialize = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        let (member, name) = match &field.ident {
+            Some(ident) => (Member::Named(ident.clone()), ident.clone()),
+            None => (
+                Member::Unnamed(syn::Index {
+                    index: i as u32,
+                    span: field.ty.span(),
+                }),
+                quote::format_ident!("_{}", i, span = field.ty.span()),
+            ),
+        };
+
+        // Like thiserror, a field named `source` is the source unless another field is marked.
+        let is_source = if transparent.is_some() {
+            true
+        } else if has_source_attr {
+            is_source_attr(field)
+        } else {
+            field.ident.as_ref().is_some_and(|ident| ident == "source")
+        };
+
+        if has_attr(field, "serialize") {
+            serialize.push(Binding {
+                member: member.clone(),
+                name: name.clone(),
+                ty: &field.ty,
+            });
+        }
+
+        if is_source {
+            let binding = Binding {
+                member: member.clone(),
+                name: name.clone(),
+                ty: &field.ty,
+            };
+            if source.replace(binding).is_some() {
+                return Err(syn::Error::new(field.span(), crate::errs::DUPE_SOURCE));
             }
-            syn::Fields::Unit => {}
-            syn::Fields::Unnamed(_) => {
-                return Err(syn::Error::new(
-                    variant.span(),
-                    crate::errs::ONLY_NAMED_FIELDS,
-                ))
+            from = field.attrs.iter().find(|attr| attr.path().is_ident("from"));
+            from_none = has_attr(field, "from_none");
+        } else if has_attr(field, "location") {
+            let binding = Binding {
+                member: member.clone(),
+                name: name.clone(),
+                ty: &field.ty,
+            };
+            if location.replace(binding).is_some() {
+                return Err(syn::Error::new(field.span(), crate::errs::DUPE_LOCATION));
+            }
+        } else if has_attr(field, "backtrace") {
+            if !cfg!(feature = "std") {
+                return Err(syn::Error::new(field.span(), crate::errs::BACKTRACE_STD));
+            }
+            let binding = Binding {
+                member: member.clone(),
+                name: name.clone(),
+                ty: &field.ty,
+            };
+            if backtrace.replace(binding).is_some() {
+                return Err(syn::Error::new(field.span(), crate::errs::DUPE_BACKTRACE));
+            }
+        } else if has_attr(field, "trace") {
+            if !cfg!(feature = "alloc") {
+                return Err(syn::Error::new(field.span(), crate::errs::TRACE_ALLOC));
             }
+            let binding = Binding {
+                member: member.clone(),
+                name: name.clone(),
+                ty: &field.ty,
+            };
+            if trace.replace(binding).is_some() {
+                return Err(syn::Error::new(field.span(), crate::errs::DUPE_TRACE));
+            }
+        } else {
+            let selector_member = match &field.ident {
+                Some(ident) => Member::Named(ident.clone()),
+                None => Member::Unnamed(syn::Index {
+                    index: selector_fields.len() as u32,
+                    span: field.ty.span(),
+                }),
+            };
+            selector_fields.push(field);
+            selector_members.push(selector_member);
+            selector_field_names.push(name.clone());
         }
+        all_field_members.push(member);
+        all_field_names.push(name);
+        all_field_tys.push(&field.ty);
+    }
 
-        let all_field_names: Vec<&Ident> = all_fields
-            .iter()
-            .map(|Field { ident, .. }| ident.as_ref().expect("cannot parse ident"))
-            .collect();
+    if let Some(from) = from {
+        if !selector_fields.is_empty() {
+            return Err(syn::Error::new(from.span(), crate::errs::FROM_FIELDS));
+        }
+    }
 
-        let selector_field_names: Vec<&Ident> = selector_fields
-            .iter()
-            .map(|Field { ident, .. }| ident.as_ref().expect("cannot parse ident"))
-            .collect();
+    for text in error_text.iter().chain(&help_text).chain(&note_text) {
+        for field in &text.fields {
+            if !all_field_names.contains(field) {
+                return Err(unknown_field(field, &all_field_names));
+            }
+        }
+    }
 
-        let variant = Sub {
-            enum_name,
-            name,
-            source,
-            selector_fields,
-            selector_field_names,
-            all_field_names,
-            error_text,
-            help_text,
-            location,
-        };
+    Ok(Sub {
+        enum_name,
+        ident,
+        name,
+        path,
+        is_tuple,
+        source,
+        selector_fields,
+        selector_members,
+        selector_field_names,
+        all_field_members,
+        all_field_names,
+        all_field_tys,
+        error_text,
+        help_text,
+        note_text,
+        location,
+        backtrace,
+        trace,
+        serialize,
+        code,
+        from: from.is_some(),
+        from_none,
+        transparent: transparent.is_some(),
+        clone,
+    })
+}
 
-        out.push(variant)
+/// An error for a format argument that is not a field, suggesting one with a similar name.
+fn unknown_field(field: &Ident, names: &[Ident]) -> syn::Error {
+    let name = field.to_string();
+    // Tuple fields are bound as `_0`, but written as `{0}`
+    if let Some(index) = name
+        .strip_prefix('_')
+        .filter(|index| index.bytes().all(|b| b.is_ascii_digit()))
+    {
+        let message = format!("{} `{index}`", crate::errs::UNKNOWN_FIELD);
+        return syn::Error::new(field.span(), message);
     }
 
-    Ok(out)
+    let mut message = format!("{} `{name}`", crate::errs::UNKNOWN_FIELD);
+    let suggestion = names
+        .iter()
+        .map(ToString::to_string)
+        .map(|candidate| (edit_distance(&name, &candidate), candidate))
+        .filter(|(distance, _)| *distance <= name.len().div_ceil(3))
+        .min();
+    if let Some((_, candidate)) = suggestion {
+        message.push_str(&format!(", did you mean `{candidate}`?"));
+    }
+    syn::Error::new(field.span(), message)
 }
 
-fn fmt_parse(s: &str) -> Result<(String, Vec<(String, usize)>), ()> {
-    use std::fmt::Write;
-
-    let mut sl = s;
-    let mut out = String::new();
-    let mut args: Vec<(String, usize)> = Vec::new();
-    loop {
-        let Some((prefix, suffix)) = sl.split_once("{") else {
-            out.push_str(sl);
-            break;
-        };
-        out.push_str(prefix);
-        sl = suffix;
+/// The number of characters to insert, remove, replace or swap with their neighbour to turn `a`
+/// into `b`, without editing any character twice.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // `d[i][j]` is the distance between the first `i` characters of `a` and the first `j` of `b`
+    let mut d: Vec<Vec<usize>> = (0..=a.len()).map(|i| vec![i; b.len() + 1]).collect();
+    d[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substitute = d[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = substitute.min(d[i - 1][j] + 1).min(d[i][j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
 
-        if let Some(("", rest)) = sl.split_once("{") {
-            out.push_str("{{");
-            sl = rest;
-            continue; // "{{"" is an escape
+/// The `#[code_prefix = "..."]` of the enum, which is empty without one.
+fn parse_code_prefix(attrs: &[syn::Attribute]) -> syn::Result<String> {
+    let mut prefix = None;
+    for attr in attrs {
+        if attr.path().is_ident("code_prefix") {
+            let value = &attr.meta.require_name_value()?.value;
+            let lit: syn::LitStr = syn::parse2(value.into_token_stream())?;
+            if prefix.replace(lit.value()).is_some() {
+                return Err(syn::Error::new(attr.span(), crate::errs::DUPE_CODE_PREFIX));
+            }
         }
+    }
+    Ok(prefix.unwrap_or_default())
+}
 
-        let (argument, rest, formatter) = if let Some((argument, rest)) = sl.split_once("}") {
-            if let Some((argument, formatter)) = argument.split_once(":") {
-                (argument, rest, format!(":{formatter}"))
-            } else {
-                (argument, rest, String::new())
+/// Codes are all or nothing, so that `code()` always has one, and have to be unique.
+///
+/// Transparent variants can go without, and forward the code of the error they wrap.
+fn check_codes(subs: &[Sub<'_>]) -> syn::Result<()> {
+    if subs.iter().all(|sub| sub.code.is_none()) {
+        return Ok(());
+    }
+    let mut seen = std::collections::HashSet::new();
+    for sub in subs {
+        match &sub.code {
+            Some(code) => {
+                if !seen.insert(code.value()) {
+                    return Err(syn::Error::new(code.span(), crate::errs::DUPE_CODE));
+                }
             }
-        } else {
-            return Err(());
-        };
+            None if sub.transparent => {}
+            None => return Err(syn::Error::new(sub.ident.span(), crate::errs::MISSING_CODE)),
+        }
+    }
+    Ok(())
+}
 
-        if let Some(("", remainder)) = rest.split_once("}") {
-            sl = remainder;
-            continue; // "{{"" is an escape
-        };
-        sl = rest;
-        write!(out, "{{{formatter}}}").unwrap();
-        let offset = argument.as_ptr() as usize - s.as_ptr() as usize;
-        args.push((String::from(argument), offset));
+/// Two `From` impls for the same source type would conflict.
+fn check_dupe_from(subs: &[Sub<'_>]) -> syn::Result<()> {
+    let mut seen = std::collections::HashSet::new();
+    for sub in subs {
+        if let (true, Some(source)) = (sub.from, &sub.source) {
+            if !seen.insert(source.ty.to_token_stream().to_string()) {
+                return Err(syn::Error::new(source.ty.span(), crate::errs::DUPE_FROM));
+            }
+        }
     }
-    Ok((out, args))
+    Ok(())
+}
+
+fn has_attr(field: &Field, name: &str) -> bool {
+    field.attrs.iter().any(|attr| attr.path().is_ident(name))
+}
+
+/// Whether the field is marked as the source, which `#[from]` and `#[from_none]` also do.
+fn is_source_attr(field: &Field) -> bool {
+    ["source", "from", "from_none"]
+        .into_iter()
+        .any(|name| has_attr(field, name))
 }
 
 #[cfg(test)]
@@ -282,5 +890,288 @@ mod tests {
     fn parse_nothing() {
         let s = "whatever {}";
         let (out, args) = fmt_parse(s).unwrap();
+
+        assert_eq!(out, "whatever {}");
+        assert_eq!(&args, &[(String::from(""), 10)]);
+    }
+
+    #[test]
+    fn parse_positional() {
+        let text: Text = syn::parse_str(r#""bad value {0} for {1:?}""#).unwrap();
+
+        assert_eq!(text.lit.to_string(), r#""bad value {} for {:?}""#);
+        let args: Vec<String> = text.args.iter().map(ToString::to_string).collect();
+        assert_eq!(args, ["_0", "_1"]);
+    }
+
+    #[test]
+    fn parse_uses() {
+        let text: Text = syn::parse_str(r#""{a:?} {{b}} {c:#x} {d:>8} {e.len()}""#).unwrap();
+
+        let uses: Vec<String> = text
+            .uses
+            .iter()
+            .map(|(field, fmt_trait)| format!("{field}: {fmt_trait}"))
+            .collect();
+        assert_eq!(
+            uses,
+            [
+                "a: :: core :: fmt :: Debug",
+                "c: :: core :: fmt :: LowerHex",
+                "d: :: core :: fmt :: Display"
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_counts() {
+        let s = "{a:>{width}} {b:*^w$.{p}} {:.1$?} {x.get(0).map(|v| { v + 1 }):?}";
+        let (out, args) = fmt_parse(s).unwrap();
+
+        assert_eq!(out, "{:>4$} {:*^5$.6$} {:.7$?} {:?}");
+        let args: Vec<_> = args.iter().map(|(arg, _)| arg.as_str()).collect();
+        assert_eq!(
+            args,
+            [
+                "a",
+                "b",
+                "",
+                "x.get(0).map(|v| { v + 1 })",
+                "width",
+                "w",
+                "p",
+                "1"
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_implicit() {
+        let text: Text = syn::parse_str(r#""{} and {:?}, {0:>{1}}""#).unwrap();
+
+        let args: Vec<String> = text.args.iter().map(ToString::to_string).collect();
+        assert_eq!(args, ["_0", "_1", "_0", "* _1"]);
+        assert_eq!(text.uses.len(), 3);
+    }
+
+    #[test]
+    fn parse_malformed() {
+        for (s, message) in [
+            (r#""{a""#, crate::errs::UNCLOSED_BRACE),
+            (r#""{a:>{w}""#, crate::errs::UNCLOSED_BRACE),
+            (r#""a}""#, crate::errs::UNMATCHED_BRACE),
+            (r#""{a:z}""#, crate::errs::BAD_FORMAT_SPEC),
+            (r#""{a:.*}""#, crate::errs::STAR_PRECISION),
+            (r#""{a:.}""#, crate::errs::EXPECTED_PRECISION),
+            (r#""{a:{}}""#, crate::errs::EMPTY_COUNT),
+            (r#""{a b}""#, crate::errs::BAD_FORMAT_ARG),
+        ] {
+            let error = syn::parse_str::<Text>(s).err().unwrap();
+            assert_eq!(error.to_string(), message, "{s}");
+        }
+    }
+
+    #[test]
+    fn parse_unknown_fields() {
+        let typo: syn::DeriveInput = syn::parse_quote! {
+            enum E {
+                #[error = "failed to read {path}"]
+                #[help = "check {fielname.len()}"]
+                A { path: String, filename: String },
+            }
+        };
+        let error = parse(&typo).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "this variant has no field `fielname`, did you mean `filename`?"
+        );
+
+        let swapped: syn::DeriveInput = syn::parse_quote! {
+            enum E {
+                #[error = "missing {kye}"]
+                A { key: String },
+            }
+        };
+        let error = parse(&swapped).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "this variant has no field `kye`, did you mean `key`?"
+        );
+
+        let index: syn::DeriveInput = syn::parse_quote! {
+            enum E {
+                #[error = "{0} {1:>{2}}"]
+                A(String, String),
+            }
+        };
+        let error = parse(&index).err().unwrap();
+        assert_eq!(error.to_string(), "this variant has no field `2`");
+    }
+
+    #[test]
+    fn parse_explicit_args() {
+        let text: Text = syn::parse_str(
+            r#""{} of {0}: {reason:>w$} {}", path.display(), name, w = *width, reason = cause,"#,
+        )
+        .unwrap();
+
+        let args: Vec<String> = text.args.iter().map(ToString::to_string).collect();
+        assert_eq!(args, ["__arg0", "__arg0", "reason", "__arg1", "* w"]);
+        let bindings: Vec<String> = text
+            .bindings
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .collect();
+        assert_eq!(bindings, ["__arg0", "__arg1", "w", "reason"]);
+        let fields: Vec<String> = text.fields.iter().map(ToString::to_string).collect();
+        assert_eq!(fields, ["path", "name", "width", "cause"]);
+        let uses: Vec<String> = text
+            .uses
+            .iter()
+            .map(|(field, _)| field.to_string())
+            .collect();
+        assert_eq!(uses, ["cause", "name"]);
+
+        for (s, message) in [
+            (r#""{}", a, b"#, crate::errs::UNUSED_FORMAT_ARG),
+            (r#""{1}", a"#, crate::errs::NO_POSITIONAL_ARG),
+            (r#""{x}", x = a, x = b"#, crate::errs::DUPE_FORMAT_ARG),
+            (r#""{x} {}", x = a, b"#, crate::errs::POSITIONAL_AFTER_NAMED),
+        ] {
+            let error = syn::parse_str::<Text>(s).err().unwrap();
+            assert_eq!(error.to_string(), message, "{s}");
+        }
+
+        let input: syn::DeriveInput = syn::parse_quote! {
+            enum E {
+                #[error("failed to read {}", path.display())]
+                #[help("retry {n} times", n = retries)]
+                A { path: std::path::PathBuf, retries: u8 },
+                #[error(transparent)]
+                B(std::io::Error),
+            }
+        };
+        let subs = parse(&input).unwrap();
+        assert_eq!(subs[0].error_text.len(), 1);
+        assert_eq!(subs[0].help_text.len(), 1);
+        assert!(subs[1].transparent);
+    }
+
+    #[test]
+    fn parse_default_help() {
+        let input: syn::DeriveInput = syn::parse_quote! {
+            #[help = "run with --verbose for more info"]
+            #[note("code {}", code)]
+            enum E {
+                #[error = "a"]
+                A { code: u8 },
+                #[error = "b"]
+                #[help = "check {path}"]
+                B { path: String, code: u8 },
+                #[error = "c"]
+                #[help(none)]
+                C { code: u8 },
+                #[error(transparent)]
+                D(std::io::Error),
+            }
+        };
+        let subs = parse(&input).unwrap();
+        let help: Vec<Vec<&str>> = subs
+            .iter()
+            .map(|sub| sub.help_text.iter().map(|text| &*text.template).collect())
+            .collect();
+        assert_eq!(
+            help,
+            [
+                vec!["run with --verbose for more info"],
+                vec!["check {path}"],
+                vec![],
+                vec![],
+            ]
+        );
+        let notes: Vec<usize> = subs.iter().map(|sub| sub.note_text.len()).collect();
+        assert_eq!(notes, [1, 1, 1, 0]);
+
+        let missing: syn::DeriveInput = syn::parse_quote! {
+            #[help = "{code}"]
+            enum E {
+                #[error = "a"]
+                A { code: u8 },
+                #[error = "b"]
+                B,
+            }
+        };
+        let error = parse(&missing).err().unwrap();
+        assert_eq!(error.to_string(), "this variant has no field `code`");
+
+        let both: syn::DeriveInput = syn::parse_quote! {
+            enum E {
+                #[error = "a"]
+                #[help(none)]
+                #[help = "b"]
+                A,
+            }
+        };
+        let error = parse(&both).err().unwrap();
+        assert_eq!(error.to_string(), crate::errs::NONE_WITH_TEXT);
+    }
+
+    #[test]
+    fn distance() {
+        assert_eq!(edit_distance("field", "field"), 0);
+        assert_eq!(edit_distance("fiel", "field"), 1);
+        assert_eq!(edit_distance("nmae", "name"), 1);
+        assert_eq!(edit_distance("kye", "key"), 1);
+        assert_eq!(edit_distance("ca", "abc"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn parse_codes() {
+        let input: syn::DeriveInput = syn::parse_quote! {
+            #[code_prefix = "E"]
+            enum E {
+                #[error = "a"]
+                #[code = "01"]
+                A,
+                #[error = "b"]
+                #[code = "02"]
+                B,
+            }
+        };
+        let codes: Vec<_> = parse(&input)
+            .unwrap()
+            .iter()
+            .map(|sub| sub.code.as_ref().unwrap().value())
+            .collect();
+        assert_eq!(codes, ["E01", "E02"]);
+    }
+
+    #[test]
+    fn parse_bad_codes() {
+        let dupe: syn::DeriveInput = syn::parse_quote! {
+            enum E {
+                #[error = "a"]
+                #[code = "E01"]
+                A,
+                #[error = "b"]
+                #[code = "E01"]
+                B,
+            }
+        };
+        let error = parse(&dupe).err().unwrap();
+        assert_eq!(error.to_string(), crate::errs::DUPE_CODE);
+
+        let missing: syn::DeriveInput = syn::parse_quote! {
+            enum E {
+                #[error = "a"]
+                #[code = "E01"]
+                A,
+                #[error = "b"]
+                B,
+            }
+        };
+        let error = parse(&missing).err().unwrap();
+        assert_eq!(error.to_string(), crate::errs::MISSING_CODE);
     }
 }
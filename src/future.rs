@@ -0,0 +1,279 @@
+//! [`Context`](crate::Context) for futures and streams of `Result`s, for the `futures` feature.
+//!
+//! The location an error gets is where `context` was called, rather than where the future
+//! happened to be polled.
+
+use crate::With;
+use core::convert::Infallible;
+use core::future::Future;
+use core::marker::PhantomData;
+use core::panic::Location;
+use core::pin::Pin;
+use core::task::{ready, Context, Poll};
+use futures_core::Stream;
+use pin_project_lite::pin_project;
+
+pub trait FutureContext<T, E>: Future<Output = Result<T, E>> + Sized {
+    #[track_caller]
+    fn context<W: With<Result<Infallible, E>, Dst>, Dst>(
+        self,
+        ctx: W,
+    ) -> ContextFuture<Self, W, Dst>;
+    #[track_caller]
+    fn with_context<W: With<Result<Infallible, E>, Dst>, Dst, F: FnOnce() -> W>(
+        self,
+        ctx: F,
+    ) -> WithContextFuture<Self, F, Dst>;
+}
+
+impl<Fut: Future<Output = Result<T, E>>, T, E> FutureContext<T, E> for Fut {
+    fn context<W: With<Result<Infallible, E>, Dst>, Dst>(
+        self,
+        ctx: W,
+    ) -> ContextFuture<Self, W, Dst> {
+        ContextFuture {
+            future: self,
+            ctx: Some(ctx),
+            location: Location::caller(),
+            dst: PhantomData,
+        }
+    }
+
+    fn with_context<W: With<Result<Infallible, E>, Dst>, Dst, F: FnOnce() -> W>(
+        self,
+        ctx: F,
+    ) -> WithContextFuture<Self, F, Dst> {
+        WithContextFuture {
+            future: self,
+            ctx: Some(ctx),
+            location: Location::caller(),
+            dst: PhantomData,
+        }
+    }
+}
+
+pin_project! {
+    /// The future returned by [`FutureContext::context`].
+    #[must_use = "futures do nothing unless polled"]
+    pub struct ContextFuture<Fut, W, Dst> {
+        #[pin]
+        future: Fut,
+        ctx: Option<W>,
+        location: &'static Location<'static>,
+        dst: PhantomData<fn() -> Dst>,
+    }
+}
+
+impl<Fut, T, E, W, Dst> Future for ContextFuture<Fut, W, Dst>
+where
+    Fut: Future<Output = Result<T, E>>,
+    W: With<Result<Infallible, E>, Dst>,
+{
+    type Output = Result<T, Dst>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        Poll::Ready(ready!(this.future.poll(cx)).map_err(|e| {
+            let ctx = this
+                .ctx
+                .take()
+                .expect("`ContextFuture` polled after completion");
+            ctx.bind_at(Err(e), this.location)
+        }))
+    }
+}
+
+pin_project! {
+    /// The future returned by [`FutureContext::with_context`].
+    #[must_use = "futures do nothing unless polled"]
+    pub struct WithContextFuture<Fut, F, Dst> {
+        #[pin]
+        future: Fut,
+        ctx: Option<F>,
+        location: &'static Location<'static>,
+        dst: PhantomData<fn() -> Dst>,
+    }
+}
+
+impl<Fut, T, E, F, W, Dst> Future for WithContextFuture<Fut, F, Dst>
+where
+    Fut: Future<Output = Result<T, E>>,
+    F: FnOnce() -> W,
+    W: With<Result<Infallible, E>, Dst>,
+{
+    type Output = Result<T, Dst>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        Poll::Ready(ready!(this.future.poll(cx)).map_err(|e| {
+            let ctx = this
+                .ctx
+                .take()
+                .expect("`WithContextFuture` polled after completion");
+            ctx().bind_at(Err(e), this.location)
+        }))
+    }
+}
+
+/// [`FutureContext`] for every error a stream yields, which is why `context` needs a `Clone`.
+///
+/// Derived selectors without fields are `Clone`, and those with fields are with `#[clone]` on the
+/// variant.
+pub trait StreamContext<T, E>: Stream<Item = Result<T, E>> + Sized {
+    #[track_caller]
+    fn context<W: With<Result<Infallible, E>, Dst> + Clone, Dst>(
+        self,
+        ctx: W,
+    ) -> ContextStream<Self, W, Dst>;
+    #[track_caller]
+    fn with_context<W: With<Result<Infallible, E>, Dst>, Dst, F: FnMut() -> W>(
+        self,
+        ctx: F,
+    ) -> WithContextStream<Self, F, Dst>;
+}
+
+impl<St: Stream<Item = Result<T, E>>, T, E> StreamContext<T, E> for St {
+    fn context<W: With<Result<Infallible, E>, Dst> + Clone, Dst>(
+        self,
+        ctx: W,
+    ) -> ContextStream<Self, W, Dst> {
+        ContextStream {
+            stream: self,
+            ctx,
+            location: Location::caller(),
+            dst: PhantomData,
+        }
+    }
+
+    fn with_context<W: With<Result<Infallible, E>, Dst>, Dst, F: FnMut() -> W>(
+        self,
+        ctx: F,
+    ) -> WithContextStream<Self, F, Dst> {
+        WithContextStream {
+            stream: self,
+            ctx,
+            location: Location::caller(),
+            dst: PhantomData,
+        }
+    }
+}
+
+pin_project! {
+    /// The stream returned by [`StreamContext::context`].
+    #[must_use = "streams do nothing unless polled"]
+    pub struct ContextStream<St, W, Dst> {
+        #[pin]
+        stream: St,
+        ctx: W,
+        location: &'static Location<'static>,
+        dst: PhantomData<fn() -> Dst>,
+    }
+}
+
+impl<St, T, E, W, Dst> Stream for ContextStream<St, W, Dst>
+where
+    St: Stream<Item = Result<T, E>>,
+    W: With<Result<Infallible, E>, Dst> + Clone,
+{
+    type Item = Result<T, Dst>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let item = ready!(this.stream.poll_next(cx));
+        Poll::Ready(
+            item.map(|item| item.map_err(|e| this.ctx.clone().bind_at(Err(e), this.location))),
+        )
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.stream.size_hint()
+    }
+}
+
+pin_project! {
+    /// The stream returned by [`StreamContext::with_context`].
+    #[must_use = "streams do nothing unless polled"]
+    pub struct WithContextStream<St, F, Dst> {
+        #[pin]
+        stream: St,
+        ctx: F,
+        location: &'static Location<'static>,
+        dst: PhantomData<fn() -> Dst>,
+    }
+}
+
+impl<St, T, E, F, W, Dst> Stream for WithContextStream<St, F, Dst>
+where
+    St: Stream<Item = Result<T, E>>,
+    F: FnMut() -> W,
+    W: With<Result<Infallible, E>, Dst>,
+{
+    type Item = Result<T, Dst>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let item = ready!(this.stream.poll_next(cx));
+        Poll::Ready(item.map(|item| item.map_err(|e| (this.ctx)().bind_at(Err(e), this.location))))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.stream.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::task::Waker;
+
+    #[derive(Clone)]
+    struct Here;
+
+    impl With<Result<Infallible, ()>, u32> for Here {
+        fn bind(self, _: Result<Infallible, ()>) -> u32 {
+            Location::caller().line()
+        }
+
+        fn bind_at(self, _: Result<Infallible, ()>, location: &'static Location<'static>) -> u32 {
+            location.line()
+        }
+    }
+
+    fn poll<F: Future>(future: F) -> F::Output {
+        let mut future = core::pin::pin!(future);
+        match future
+            .as_mut()
+            .poll(&mut Context::from_waker(Waker::noop()))
+        {
+            Poll::Ready(output) => output,
+            Poll::Pending => unreachable!(),
+        }
+    }
+
+    struct Once(Option<Result<(), ()>>);
+
+    impl Stream for Once {
+        type Item = Result<(), ()>;
+
+        fn poll_next(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            Poll::Ready(self.0.take())
+        }
+    }
+
+    #[test]
+    fn created_not_polled() {
+        let line = line!();
+        let future = core::future::ready(Err::<(), _>(())).context(Here);
+        let lazy = core::future::ready(Err::<(), _>(())).with_context(|| Here);
+        assert_eq!(poll(future), Err(line + 1));
+        assert_eq!(poll(lazy), Err(line + 2));
+    }
+
+    #[test]
+    fn stream() {
+        let line = line!();
+        let stream = core::pin::pin!(Once(Some(Err(()))).context(Here));
+        let next = stream.poll_next(&mut Context::from_waker(Waker::noop()));
+        assert_eq!(next, Poll::Ready(Some(Err(line + 1))));
+    }
+}
@@ -0,0 +1,153 @@
+//! Structured output of error chains, for the `serde` feature.
+
+use crate::{Kind, Lines, Link, Reportable};
+use alloc::string::String;
+use core::fmt;
+use core::panic::Location;
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+/// Serializes an error and its chain of sources as JSON.
+///
+/// See [`Serialized`] for the schema.
+pub fn to_json<E: Reportable>(error: &E) -> Result<String, serde_json::Error> {
+    serde_json::to_string(&Serialized::new(error))
+}
+
+/// An error and its chain of sources, in a structured form that does not depend on `Display`.
+///
+/// Every error in the chain is an object with these keys:
+///
+/// - `enum`: the name of the type, or `null` if it is not a `Diagnostic`
+/// - `variant`: the name of the variant, or `null` for structs
+/// - `message`: the error's own message
+/// - `fields`: the fields marked `#[serialize]`, by name, with `null` for any that fail to
+///   serialize
+/// - `location`: an object with `file`, `line` and `column`, or `null`
+/// - `help`, `notes`: arrays of lines
+/// - `source`: the next error in the chain, or `null`
+pub struct Serialized<'a> {
+    link: Link<'a>,
+}
+
+impl<'a> Serialized<'a> {
+    pub fn new<E: Reportable>(error: &'a E) -> Self {
+        Self {
+            link: Link {
+                error,
+                diagnostic: error.as_diagnostic(),
+            },
+        }
+    }
+}
+
+impl Serialize for Serialized<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let link = self.link;
+        let diagnostic = link.diagnostic();
+
+        let mut map = serializer.serialize_map(Some(8))?;
+        map.serialize_entry("enum", &diagnostic.and_then(|d| d.enum_name()))?;
+        map.serialize_entry("variant", &diagnostic.and_then(|d| d.variant_name()))?;
+        map.serialize_entry("message", &Text(Message(link)))?;
+        map.serialize_entry(
+            "fields",
+            &diagnostic.map(|d| d.fields()).unwrap_or_default(),
+        )?;
+        map.serialize_entry("location", &link.location().map(SerializedLocation))?;
+        map.serialize_entry("help", &SerializedLines(link.lines(Kind::Help)))?;
+        map.serialize_entry("notes", &SerializedLines(link.lines(Kind::Note)))?;
+        let source = link.chain().nth(1).map(|link| Serialized { link });
+        map.serialize_entry("source", &source)?;
+        map.end()
+    }
+}
+
+/// Serializes as what it displays.
+struct Text<T>(T);
+
+impl<T: fmt::Display> Serialize for Text<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&self.0)
+    }
+}
+
+/// Displays the error's own message, without the chain that `#[top_level]` adds.
+struct Message<'a>(Link<'a>);
+
+impl fmt::Display for Message<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.write_message(f)
+    }
+}
+
+struct SerializedLines<'a>(Option<Lines<'a>>);
+
+impl Serialize for SerializedLines<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.into_iter().flat_map(Lines::iter).map(Text))
+    }
+}
+
+struct SerializedLocation<'a>(&'a Location<'static>);
+
+impl Serialize for SerializedLocation<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(3))?;
+        map.serialize_entry("file", self.0.file())?;
+        map.serialize_entry("line", &self.0.line())?;
+        map.serialize_entry("column", &self.0.column())?;
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Diagnostic, Help};
+    use core::error::Error;
+    use core::fmt::Write;
+
+    #[derive(Debug)]
+    struct Leaf;
+
+    impl fmt::Display for Leaf {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("leaf")
+        }
+    }
+
+    impl Error for Leaf {
+        #[cfg(feature = "nightly")]
+        fn provide<'a>(&'a self, request: &mut core::error::Request<'a>) {
+            request.provide_ref::<dyn Diagnostic>(self);
+        }
+    }
+
+    impl Diagnostic for Leaf {
+        fn write_help_line(&self, _: usize, f: &mut dyn Write) -> fmt::Result {
+            f.write_str("try \"again\"")
+        }
+
+        fn help(&self) -> Option<Help<'_>> {
+            Some(Help::new(self, "Leaf", 1))
+        }
+
+        fn enum_name(&self) -> Option<&'static str> {
+            Some("Leaf")
+        }
+
+        fn fields(&self) -> serde_json::Map<String, serde_json::Value> {
+            let mut fields = serde_json::Map::new();
+            fields.insert(String::from("n"), serde_json::Value::from(3));
+            fields
+        }
+    }
+
+    #[test]
+    fn leaf() {
+        assert_eq!(
+            to_json(&Leaf).unwrap(),
+            r#"{"enum":"Leaf","variant":null,"message":"leaf","fields":{"n":3},"location":null,"help":["try \"again\""],"notes":[],"source":null}"#
+        );
+    }
+}
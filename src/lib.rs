@@ -1,20 +1,51 @@
-#![feature(try_trait_v2)]
+#![cfg_attr(
+    feature = "nightly",
+    feature(try_trait_v2, error_generic_member_access)
+)]
+#![no_std]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+use core::convert::Infallible;
+use core::error::Error;
 use core::fmt;
+use core::fmt::Write;
+#[cfg(feature = "nightly")]
 use core::ops::{ControlFlow, Try};
+use core::panic::Location;
 
 pub trait Context<T, Src> {
     #[track_caller]
     fn context<W: With<Src, Dst>, Dst>(self, ctx: W) -> Result<T, Dst>;
     #[track_caller]
     fn with_context<W: With<Src, Dst>, Dst>(self, ctx: impl FnMut() -> W) -> Result<T, Dst>;
+    /// Adds the caller to the error's [`Trace`] without wrapping it.
+    #[cfg(feature = "alloc")]
+    #[track_caller]
+    fn trace(self) -> Self
+    where
+        Src: Traced;
 }
 
 pub trait With<Src, Dst> {
     #[track_caller]
     fn bind(self, residual: Src) -> Dst;
+
+    /// Like `bind` called from `location`, for when the error is made somewhere else than where
+    /// the context was given, like in a future. The default ignores `location`.
+    fn bind_at(self, residual: Src, location: &'static Location<'static>) -> Dst
+    where
+        Self: Sized,
+    {
+        let _ = location;
+        self.bind(residual)
+    }
 }
 
+#[cfg(feature = "nightly")]
 impl<T: Try> Context<T::Output, T::Residual> for T {
     fn context<W: With<T::Residual, Dst>, Dst>(self, ctx: W) -> Result<T::Output, Dst> {
         match self.branch() {
@@ -32,22 +63,1360 @@ impl<T: Try> Context<T::Output, T::Residual> for T {
             ControlFlow::Break(cause) => Err(ctx().bind(cause)),
         }
     }
+
+    #[cfg(feature = "alloc")]
+    fn trace(self) -> Self
+    where
+        T::Residual: Traced,
+    {
+        match self.branch() {
+            ControlFlow::Continue(v) => T::from_output(v),
+            ControlFlow::Break(mut cause) => {
+                cause.record(Location::caller());
+                T::from_residual(cause)
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "nightly"))]
+impl<T, E> Context<T, Result<Infallible, E>> for Result<T, E> {
+    fn context<W: With<Result<Infallible, E>, Dst>, Dst>(self, ctx: W) -> Result<T, Dst> {
+        match self {
+            Ok(v) => Ok(v),
+            Err(e) => Err(ctx.bind(Err(e))),
+        }
+    }
+
+    fn with_context<W: With<Result<Infallible, E>, Dst>, Dst>(
+        self,
+        mut ctx: impl FnMut() -> W,
+    ) -> Result<T, Dst> {
+        match self {
+            Ok(v) => Ok(v),
+            Err(e) => Err(ctx().bind(Err(e))),
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    fn trace(self) -> Self
+    where
+        Result<Infallible, E>: Traced,
+    {
+        match self {
+            Ok(v) => Ok(v),
+            Err(e) => {
+                let mut residual = Err(e);
+                residual.record(Location::caller());
+                residual.map(|never| match never {})
+            }
+        }
+    }
 }
 
+#[cfg(not(feature = "nightly"))]
+impl<T> Context<T, Option<Infallible>> for Option<T> {
+    fn context<W: With<Option<Infallible>, Dst>, Dst>(self, ctx: W) -> Result<T, Dst> {
+        match self {
+            Some(v) => Ok(v),
+            None => Err(ctx.bind(None)),
+        }
+    }
+
+    fn with_context<W: With<Option<Infallible>, Dst>, Dst>(
+        self,
+        mut ctx: impl FnMut() -> W,
+    ) -> Result<T, Dst> {
+        match self {
+            Some(v) => Ok(v),
+            None => Err(ctx().bind(None)),
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    fn trace(self) -> Self
+    where
+        Option<Infallible>: Traced,
+    {
+        self
+    }
+}
+
+/// Turns a `None` into an error, under names that do not clash with `Option::context` from
+/// [`Context`].
+pub trait OptionExt<T> {
+    #[track_caller]
+    fn ok_or_ctx<W: With<Option<Infallible>, Dst>, Dst>(self, ctx: W) -> Result<T, Dst>;
+    #[track_caller]
+    fn ok_or_else_ctx<W: With<Option<Infallible>, Dst>, Dst>(
+        self,
+        ctx: impl FnOnce() -> W,
+    ) -> Result<T, Dst>;
+}
+
+impl<T> OptionExt<T> for Option<T> {
+    fn ok_or_ctx<W: With<Option<Infallible>, Dst>, Dst>(self, ctx: W) -> Result<T, Dst> {
+        match self {
+            Some(v) => Ok(v),
+            None => Err(ctx.bind(None)),
+        }
+    }
+
+    fn ok_or_else_ctx<W: With<Option<Infallible>, Dst>, Dst>(
+        self,
+        ctx: impl FnOnce() -> W,
+    ) -> Result<T, Dst> {
+        match self {
+            Some(v) => Ok(v),
+            None => Err(ctx().bind(None)),
+        }
+    }
+}
+
+/// The source of an error made from a `None`, which has nothing more to say.
+///
+/// A source marked `#[from_none]` is made with `Default`, so that its selector also works on an
+/// `Option`, and this is the type to give it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NoneError;
+
+impl fmt::Display for NoneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the value was `None`")
+    }
+}
+
+impl Error for NoneError {}
+
+impl Diagnostic for NoneError {}
+
 pub use error_derive::Error;
 
-pub struct Help {
-    msg: String,
+#[cfg(feature = "futures")]
+pub mod future;
+#[cfg(feature = "futures")]
+pub use future::{FutureContext, StreamContext};
+#[cfg(feature = "serde")]
+mod json;
+#[cfg(feature = "registry")]
+pub mod registry;
+#[cfg(feature = "serde")]
+pub use json::{to_json, Serialized};
+#[cfg(feature = "alloc")]
+mod trace;
+#[cfg(feature = "alloc")]
+pub use trace::{Trace, Traced};
+
+/// What an error knows about itself beyond its message, implemented by the derive.
+///
+/// On nightly errors without lifetimes or type parameters also provide themselves as a
+/// `dyn Diagnostic` through `Error::provide`. On stable this trait is the only way to get at it,
+/// and the chain can only be followed through sources that implement it.
+pub trait Diagnostic: Error {
+    /// Writes the error's own message, without its code, location or sources.
+    ///
+    /// This is what `Display` prints, unless it also prints the chain, like `#[top_level]` does.
+    fn write_message(&self, f: &mut dyn Write) -> fmt::Result {
+        write!(f, "{self}")
+    }
+
+    /// Writes line `index` of the help, of which `help` says how many there are.
+    fn write_help_line(&self, index: usize, f: &mut dyn Write) -> fmt::Result {
+        let _ = (index, f);
+        Ok(())
+    }
+
+    /// Writes line `index` of the notes, of which `note` says how many there are.
+    fn write_note_line(&self, index: usize, f: &mut dyn Write) -> fmt::Result {
+        let _ = (index, f);
+        Ok(())
+    }
+
+    /// The help text, with one line per `#[help]`.
+    fn help(&self) -> Option<Help<'_>> {
+        None
+    }
+
+    /// The notes, with one line per `#[note]`.
+    fn note(&self) -> Option<Note<'_>> {
+        None
+    }
+
+    fn location(&self) -> Option<&'static Location<'static>> {
+        None
+    }
+
+    /// The stable identifier of this error, from `#[code = "..."]`.
+    fn code(&self) -> Option<Code> {
+        None
+    }
+
+    /// The stack captured when the error was created, from a `#[backtrace]` field.
+    #[cfg(feature = "std")]
+    fn backtrace(&self) -> Option<&std::backtrace::Backtrace> {
+        None
+    }
+
+    /// The places this error was propagated through, from a `#[trace]` field.
+    #[cfg(feature = "alloc")]
+    fn trace(&self) -> Option<&Trace> {
+        None
+    }
+
+    /// The `#[trace]` field, which [`Context::trace`] adds to.
+    #[cfg(feature = "alloc")]
+    fn trace_mut(&mut self) -> Option<&mut Trace> {
+        None
+    }
+
+    /// The source of this error, if it is also a `Diagnostic`.
+    fn diagnostic_source(&self) -> Option<&dyn Diagnostic> {
+        None
+    }
+
+    /// The name of the type, for [`to_json`].
+    #[cfg(feature = "serde")]
+    fn enum_name(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// The name of the variant, for [`to_json`]. Structs have none.
+    #[cfg(feature = "serde")]
+    fn variant_name(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// The fields marked `#[serialize]`, for [`to_json`].
+    ///
+    /// A field that fails to serialize, like a map with keys that are not strings, is `null`.
+    #[cfg(feature = "serde")]
+    fn fields(&self) -> serde_json::Map<alloc::string::String, serde_json::Value> {
+        serde_json::Map::new()
+    }
 }
 
-impl Help {
-    pub fn new(msg: String) -> Self {
-        Self { msg }
+#[cfg(feature = "alloc")]
+impl<T: Diagnostic + ?Sized> Diagnostic for alloc::boxed::Box<T>
+where
+    Self: Error,
+{
+    fn write_message(&self, f: &mut dyn Write) -> fmt::Result {
+        (**self).write_message(f)
+    }
+
+    fn write_help_line(&self, index: usize, f: &mut dyn Write) -> fmt::Result {
+        (**self).write_help_line(index, f)
+    }
+
+    fn write_note_line(&self, index: usize, f: &mut dyn Write) -> fmt::Result {
+        (**self).write_note_line(index, f)
+    }
+
+    fn help(&self) -> Option<Help<'_>> {
+        (**self).help()
+    }
+
+    fn note(&self) -> Option<Note<'_>> {
+        (**self).note()
+    }
+
+    fn location(&self) -> Option<&'static Location<'static>> {
+        (**self).location()
+    }
+
+    fn code(&self) -> Option<Code> {
+        (**self).code()
+    }
+
+    #[cfg(feature = "std")]
+    fn backtrace(&self) -> Option<&std::backtrace::Backtrace> {
+        (**self).backtrace()
+    }
+
+    fn trace(&self) -> Option<&Trace> {
+        (**self).trace()
+    }
+
+    fn trace_mut(&mut self) -> Option<&mut Trace> {
+        (**self).trace_mut()
+    }
+
+    fn diagnostic_source(&self) -> Option<&dyn Diagnostic> {
+        (**self).diagnostic_source()
+    }
+
+    #[cfg(feature = "serde")]
+    fn enum_name(&self) -> Option<&'static str> {
+        (**self).enum_name()
+    }
+
+    #[cfg(feature = "serde")]
+    fn variant_name(&self) -> Option<&'static str> {
+        (**self).variant_name()
+    }
+
+    #[cfg(feature = "serde")]
+    fn fields(&self) -> serde_json::Map<alloc::string::String, serde_json::Value> {
+        (**self).fields()
     }
 }
 
-impl fmt::Display for Help {
+/// Everything but `trace_mut`, which a shared reference can not give out.
+impl<T: Diagnostic + ?Sized> Diagnostic for &T {
+    fn write_message(&self, f: &mut dyn Write) -> fmt::Result {
+        (**self).write_message(f)
+    }
+
+    fn write_help_line(&self, index: usize, f: &mut dyn Write) -> fmt::Result {
+        (**self).write_help_line(index, f)
+    }
+
+    fn write_note_line(&self, index: usize, f: &mut dyn Write) -> fmt::Result {
+        (**self).write_note_line(index, f)
+    }
+
+    fn help(&self) -> Option<Help<'_>> {
+        (**self).help()
+    }
+
+    fn note(&self) -> Option<Note<'_>> {
+        (**self).note()
+    }
+
+    fn location(&self) -> Option<&'static Location<'static>> {
+        (**self).location()
+    }
+
+    fn code(&self) -> Option<Code> {
+        (**self).code()
+    }
+
+    #[cfg(feature = "std")]
+    fn backtrace(&self) -> Option<&std::backtrace::Backtrace> {
+        (**self).backtrace()
+    }
+
+    #[cfg(feature = "alloc")]
+    fn trace(&self) -> Option<&Trace> {
+        (**self).trace()
+    }
+
+    fn diagnostic_source(&self) -> Option<&dyn Diagnostic> {
+        (**self).diagnostic_source()
+    }
+
+    #[cfg(feature = "serde")]
+    fn enum_name(&self) -> Option<&'static str> {
+        (**self).enum_name()
+    }
+
+    #[cfg(feature = "serde")]
+    fn variant_name(&self) -> Option<&'static str> {
+        (**self).variant_name()
+    }
+
+    #[cfg(feature = "serde")]
+    fn fields(&self) -> serde_json::Map<alloc::string::String, serde_json::Value> {
+        (**self).fields()
+    }
+}
+
+/// A stable identifier for an error, which does not change with its message.
+///
+/// On nightly this is also provided through `Error::provide`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Code(&'static str);
+
+impl Code {
+    pub const fn new(code: &'static str) -> Self {
+        Self(code)
+    }
+
+    pub const fn as_str(&self) -> &'static str {
+        self.0
+    }
+}
+
+impl fmt::Display for Code {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Display::fmt(&self.msg, f)
+        f.write_str(self.0)
+    }
+}
+
+/// The help text of an error, which is only formatted when it is displayed.
+///
+/// Displaying it writes every line prefixed with `Help: `.
+#[derive(Clone, Copy)]
+pub struct Help<'a> {
+    lines: Lines<'a>,
+}
+
+impl<'a> Help<'a> {
+    /// `len` lines written by `write_help_line` of `diagnostic`, which is the variant `origin`.
+    pub fn new(diagnostic: &'a dyn Diagnostic, origin: &'static str, len: usize) -> Self {
+        Self {
+            lines: Lines {
+                diagnostic,
+                origin,
+                len,
+                kind: Kind::Help,
+            },
+        }
+    }
+
+    /// The variant the help comes from, like `Enum::Variant`.
+    pub fn origin(&self) -> &'static str {
+        self.lines.origin
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = Line<'a>> {
+        self.lines.iter()
+    }
+}
+
+impl fmt::Display for Help<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.lines.iter().try_for_each(|line| line.write(f))
+    }
+}
+
+/// The notes of an error, which are only formatted when they are displayed.
+///
+/// Displaying them writes every line prefixed with `Note: `.
+#[derive(Clone, Copy)]
+pub struct Note<'a> {
+    lines: Lines<'a>,
+}
+
+impl<'a> Note<'a> {
+    /// `len` lines written by `write_note_line` of `diagnostic`, which is the variant `origin`.
+    pub fn new(diagnostic: &'a dyn Diagnostic, origin: &'static str, len: usize) -> Self {
+        Self {
+            lines: Lines {
+                diagnostic,
+                origin,
+                len,
+                kind: Kind::Note,
+            },
+        }
+    }
+
+    /// The variant the notes come from, like `Enum::Variant`.
+    pub fn origin(&self) -> &'static str {
+        self.lines.origin
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = Line<'a>> {
+        self.lines.iter()
+    }
+}
+
+impl fmt::Display for Note<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.lines.iter().try_for_each(|line| line.write(f))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Help,
+    Note,
+}
+
+#[derive(Clone, Copy)]
+struct Lines<'a> {
+    diagnostic: &'a dyn Diagnostic,
+    origin: &'static str,
+    len: usize,
+    kind: Kind,
+}
+
+impl<'a> Lines<'a> {
+    fn iter(self) -> impl Iterator<Item = Line<'a>> {
+        (0..self.len).map(move |index| Line { lines: self, index })
+    }
+}
+
+/// One line of help or notes, without its `Help: ` or `Note: ` prefix.
+#[derive(Clone, Copy)]
+pub struct Line<'a> {
+    lines: Lines<'a>,
+    index: usize,
+}
+
+impl Line<'_> {
+    /// The variant the line comes from, like `Enum::Variant`.
+    pub fn origin(&self) -> &'static str {
+        self.lines.origin
+    }
+
+    /// Which `#[help]` or `#[note]` of its variant this is.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Writes the line with its prefix, as a `Help` or `Note` displays it.
+    fn write(self, f: &mut dyn Write) -> fmt::Result {
+        let prefix = match self.lines.kind {
+            Kind::Help => "Help: ",
+            Kind::Note => "Note: ",
+        };
+        writeln!(f, "{prefix}{self}")
+    }
+}
+
+impl fmt::Display for Line<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Lines {
+            diagnostic, kind, ..
+        } = self.lines;
+        match kind {
+            Kind::Help => diagnostic.write_help_line(self.index, f),
+            Kind::Note => diagnostic.write_note_line(self.index, f),
+        }
+    }
+}
+
+#[doc(hidden)]
+pub mod __private {
+    use super::{Diagnostic, Link, Order};
+    use core::error::Error;
+    use core::fmt;
+
+    /// Writes the help and notes of every error in the chain and any backtrace, for
+    /// `#[top_level]`, after a blank line if there is anything to write.
+    pub fn write_footer(
+        f: &mut dyn fmt::Write,
+        error: &dyn Error,
+        diagnostic: Option<&dyn Diagnostic>,
+    ) -> fmt::Result {
+        let mut f = super::Separated {
+            f,
+            separator: Some("\n"),
+        };
+        super::write_footer(&mut f, Link { error, diagnostic }, Order::default())
+    }
+
+    /// Writes every source of the error with its location, one per line, for `#[display_chain]`.
+    pub fn write_causes(
+        mut f: &mut dyn fmt::Write,
+        error: &dyn Error,
+        diagnostic: Option<&dyn Diagnostic>,
+    ) -> fmt::Result {
+        for link in (Link { error, diagnostic }).chain().skip(1) {
+            f.write_str("Caused by: ")?;
+            link.write(&mut f)?;
+            f.write_char('\n')?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "std")]
+    pub use std::backtrace::Backtrace;
+
+    #[cfg(feature = "registry")]
+    pub use linkme;
+
+    #[cfg(feature = "registry")]
+    #[linkme::distributed_slice]
+    pub static REGISTRY: [crate::registry::ErrorType];
+
+    #[cfg(feature = "serde")]
+    pub mod json {
+        pub use alloc::string::String;
+        pub use serde::Serialize;
+        pub use serde_json::to_value;
+
+        pub type Map = serde_json::Map<String, serde_json::Value>;
+    }
+
+    /// Gets a field as a `&dyn Diagnostic` if its type is one, through autoref specialization.
+    ///
+    /// In generic code this always falls back to `NotDiagnostic`.
+    pub struct Wrap<'a, T>(pub &'a T);
+
+    pub trait AsDiagnostic<'a> {
+        fn as_diagnostic(&self) -> Option<&'a dyn Diagnostic>;
+    }
+
+    impl<'a, T: Diagnostic> AsDiagnostic<'a> for Wrap<'a, T> {
+        fn as_diagnostic(&self) -> Option<&'a dyn Diagnostic> {
+            Some(self.0)
+        }
+    }
+
+    pub trait NotDiagnostic<'a> {
+        fn as_diagnostic(&self) -> Option<&'a dyn Diagnostic>;
+    }
+
+    impl<'a, T> NotDiagnostic<'a> for &Wrap<'a, T> {
+        fn as_diagnostic(&self) -> Option<&'a dyn Diagnostic> {
+            None
+        }
+    }
+
+    /// The same as `Wrap` for `&mut`, which is taken by value so that it can be given out.
+    pub struct WrapMut<'a, T>(pub &'a mut T);
+
+    pub trait IntoDiagnosticMut<'a> {
+        fn into_diagnostic_mut(self) -> Option<&'a mut dyn Diagnostic>;
+    }
+
+    impl<'a, T: Diagnostic> IntoDiagnosticMut<'a> for WrapMut<'a, T> {
+        fn into_diagnostic_mut(self) -> Option<&'a mut dyn Diagnostic> {
+            Some(self.0)
+        }
+    }
+
+    pub trait NotDiagnosticMut<'a> {
+        fn into_diagnostic_mut(self) -> Option<&'a mut dyn Diagnostic>;
+    }
+
+    impl<'a, T> NotDiagnosticMut<'a> for &WrapMut<'a, T> {
+        fn into_diagnostic_mut(self) -> Option<&'a mut dyn Diagnostic> {
+            None
+        }
+    }
+}
+
+/// Renders an error together with its chain of sources.
+///
+/// Every error in the chain is printed exactly once, with its location if it has one,
+/// followed by the help and notes of the whole chain. Each error is printed with
+/// [`Diagnostic::write_message`], and errors that are not a `Diagnostic` are expected to display
+/// only their own message, and not that of their source.
+pub struct Report<E> {
+    error: E,
+    style: Style,
+    order: Order,
+}
+
+/// How a [`Report`] lays out the chain of errors.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Style {
+    /// Every error on one line, separated by `: `. Help and notes are left out.
+    SingleLine,
+    /// The error, followed by a numbered list of its causes.
+    #[default]
+    MultiLine,
+    /// Every cause indented below the error it caused.
+    Tree,
+}
+
+/// Which end of the chain the help and notes of a [`Report`] start from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Order {
+    /// The error itself first, and the root cause last.
+    #[default]
+    OutermostFirst,
+    /// The root cause first, and the error itself last.
+    InnermostFirst,
+}
+
+/// An error that a [`Report`] can render, which is any [`Diagnostic`].
+///
+/// On nightly, sources in the chain that are not a `Diagnostic` are asked for the rest through
+/// `Error::provide`.
+pub trait Reportable: Error {
+    #[doc(hidden)]
+    fn as_diagnostic(&self) -> Option<&dyn Diagnostic>;
+}
+
+impl<E: Diagnostic> Reportable for E {
+    fn as_diagnostic(&self) -> Option<&dyn Diagnostic> {
+        Some(self)
+    }
+}
+
+impl<E> Report<E> {
+    pub fn new(error: E) -> Self {
+        Self {
+            error,
+            style: Style::default(),
+            order: Order::default(),
+        }
+    }
+
+    pub fn style(mut self, style: Style) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// Sets which end of the chain the help and notes start from.
+    pub fn order(mut self, order: Order) -> Self {
+        self.order = order;
+        self
+    }
+
+    pub fn into_inner(self) -> E {
+        self.error
+    }
+}
+
+impl<E: Reportable> Report<E> {
+    /// Every line of help in the chain, without repeating the same text twice.
+    pub fn help(&self) -> impl Iterator<Item = Line<'_>> {
+        hints(self.link(), Kind::Help, self.order)
+    }
+
+    /// Every line of notes in the chain, without repeating the same text twice.
+    pub fn notes(&self) -> impl Iterator<Item = Line<'_>> {
+        hints(self.link(), Kind::Note, self.order)
+    }
+
+    fn link(&self) -> Link<'_> {
+        Link {
+            error: &self.error,
+            diagnostic: self.error.as_diagnostic(),
+        }
+    }
+}
+
+impl<E: Reportable> From<E> for Report<E> {
+    fn from(error: E) -> Self {
+        Self::new(error)
+    }
+}
+
+impl<E: Reportable> fmt::Display for Report<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        render(f, self.style, self.order, self.link())
+    }
+}
+
+impl<E> fmt::Debug for Report<E>
+where
+    Self: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+fn render(f: &mut fmt::Formatter<'_>, style: Style, order: Order, link: Link<'_>) -> fmt::Result {
+    let chain = || link.chain();
+
+    // Like rustc, the error leads with its code
+    if let Some(code) = link.code() {
+        write!(f, "error[{code}]: ")?;
+    }
+
+    match style {
+        Style::SingleLine => {
+            for (i, link) in chain().enumerate() {
+                if i > 0 {
+                    f.write_str(": ")?;
+                }
+                link.write(f)?;
+            }
+            return Ok(());
+        }
+        Style::MultiLine => {
+            for (i, link) in chain().enumerate() {
+                match i {
+                    0 => link.write(&mut Indented::new(f, ""))?,
+                    _ => {
+                        if i == 1 {
+                            f.write_str("\n\nCaused by:")?;
+                        }
+                        write!(f, "\n{i:>5}: ")?;
+                        link.write(&mut Indented::new(f, "       "))?;
+                    }
+                }
+            }
+        }
+        Style::Tree => {
+            for (i, link) in chain().enumerate() {
+                if i > 0 {
+                    f.write_char('\n')?;
+                    for _ in 1..i {
+                        f.write_str(TREE_INDENT)?;
+                    }
+                    f.write_str("└─ ")?;
+                }
+                link.write(&mut Indented::new(f, TREE_INDENT).depth(i))?;
+            }
+        }
+    }
+
+    // Only separated from the chain if there is anything to write.
+    let mut f = Separated {
+        f,
+        separator: Some("\n\n"),
+    };
+    write_footer(&mut f, link, order)
+}
+
+/// Writes the help and then the notes of the whole chain, then where it was propagated through,
+/// and then the innermost backtrace if one was captured.
+fn write_footer(f: &mut dyn Write, link: Link<'_>, order: Order) -> fmt::Result {
+    for kind in [Kind::Help, Kind::Note] {
+        for line in hints(link, kind, order) {
+            line.write(f)?;
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    write_trace(f, link)?;
+
+    // `capture` only records frames when `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` is set.
+    #[cfg(feature = "std")]
+    if let Some(backtrace) = link
+        .chain()
+        .filter_map(Link::backtrace)
+        .filter(|backtrace| backtrace.status() == std::backtrace::BacktraceStatus::Captured)
+        .last()
+    {
+        let traced = link.chain().any(|link| link.trace().is_some());
+        if traced
+            || [Kind::Help, Kind::Note]
+                .into_iter()
+                .any(|kind| hints(link, kind, order).next().is_some())
+        {
+            f.write_char('\n')?;
+        }
+        write!(f, "Stack backtrace:\n{backtrace}")?;
+    }
+    Ok(())
+}
+
+/// Writes the traces of the whole chain as one, from where the innermost error was created.
+#[cfg(feature = "alloc")]
+fn write_trace(f: &mut dyn Write, link: Link<'_>) -> fmt::Result {
+    let mut traces: alloc::vec::Vec<_> = link.chain().filter_map(Link::trace).collect();
+    if traces.is_empty() {
+        return Ok(());
+    }
+    traces.reverse();
+    f.write_str("Propagated through: ")?;
+    for (i, trace) in traces.into_iter().enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        write!(f, "{trace}")?;
+    }
+    f.write_char('\n')
+}
+
+/// The help or notes of every error in the chain, in `order`, skipping lines whose text was
+/// already seen.
+#[cfg(feature = "alloc")]
+fn hints(link: Link<'_>, kind: Kind, order: Order) -> impl Iterator<Item = Line<'_>> {
+    let mut links: alloc::vec::Vec<_> = link.chain().collect();
+    if order == Order::InnermostFirst {
+        links.reverse();
+    }
+    let mut seen = alloc::collections::BTreeSet::new();
+    let lines: alloc::vec::Vec<_> = links
+        .into_iter()
+        .filter_map(|link| link.lines(kind))
+        .flat_map(Lines::iter)
+        .filter(|line| seen.insert(alloc::string::ToString::to_string(line)))
+        .collect();
+    lines.into_iter()
+}
+
+/// The same as the above without `alloc`, which walks the chain again for every line and
+/// compares the text of every earlier line with it as it is written. Chains are short, and so is
+/// help.
+#[cfg(not(feature = "alloc"))]
+fn hints(link: Link<'_>, kind: Kind, order: Order) -> impl Iterator<Item = Line<'_>> {
+    let len = link.chain().count();
+    let nth = move |i| match order {
+        Order::OutermostFirst => link.chain().nth(i),
+        Order::InnermostFirst => link.chain().nth(len - 1 - i),
+    };
+    let all = move || {
+        (0..len)
+            .filter_map(nth)
+            .filter_map(move |link| link.lines(kind))
+            .flat_map(Lines::iter)
+    };
+    all()
+        .enumerate()
+        .filter(move |(i, line)| !all().take(*i).any(|seen| same_text(&seen, line)))
+        .map(|(_, line)| line)
+}
+
+/// Whether two values display as the same text, without allocating.
+///
+/// Every piece `a` is written in is looked for at the same offset in `b`, by formatting `b` again.
+#[cfg_attr(feature = "alloc", allow(dead_code))]
+fn same_text(a: &dyn fmt::Display, b: &dyn fmt::Display) -> bool {
+    /// Checks every piece written against `other`.
+    struct Compare<'a> {
+        other: &'a dyn fmt::Display,
+        offset: usize,
+    }
+
+    impl Write for Compare<'_> {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            if s.is_empty() {
+                return Ok(());
+            }
+            let mut find = Find {
+                expected: s.as_bytes(),
+                skip: self.offset,
+                matched: 0,
+            };
+            // `Find` stops the formatting early either way.
+            let _ = write!(find, "{}", self.other);
+            if find.matched != s.len() {
+                return Err(fmt::Error);
+            }
+            self.offset += s.len();
+            Ok(())
+        }
+    }
+
+    /// Matches what is written after the first `skip` bytes against `expected`.
+    struct Find<'a> {
+        expected: &'a [u8],
+        skip: usize,
+        matched: usize,
+    }
+
+    impl Write for Find<'_> {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let skip = self.skip.min(s.len());
+            self.skip -= skip;
+            let s = &s.as_bytes()[skip..];
+            let expected = &self.expected[self.matched..];
+            let len = s.len().min(expected.len());
+            if s[..len] != expected[..len] {
+                return Err(fmt::Error);
+            }
+            self.matched += len;
+            if self.matched == self.expected.len() {
+                return Err(fmt::Error);
+            }
+            Ok(())
+        }
+    }
+
+    /// Counts the bytes written.
+    struct Len(usize);
+
+    impl Write for Len {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.0 += s.len();
+            Ok(())
+        }
+    }
+
+    let mut compare = Compare {
+        other: b,
+        offset: 0,
+    };
+    let mut len = Len(0);
+    write!(compare, "{a}").is_ok() && write!(len, "{b}").is_ok() && len.0 == compare.offset
+}
+
+const TREE_INDENT: &str = "   ";
+
+/// An error in the chain, and the same error as a `Diagnostic` if it is one.
+#[derive(Clone, Copy)]
+struct Link<'a> {
+    error: &'a (dyn Error + 'a),
+    diagnostic: Option<&'a (dyn Diagnostic + 'a)>,
+}
+
+impl<'a> Link<'a> {
+    /// This error and its sources.
+    fn chain(self) -> impl Iterator<Item = Link<'a>> {
+        core::iter::successors(Some(self), |link| {
+            Some(Link {
+                error: link.error.source()?,
+                diagnostic: link.diagnostic.and_then(|d| d.diagnostic_source()),
+            })
+        })
+    }
+
+    /// The error's own message, and its location.
+    fn write(self, f: &mut impl Write) -> fmt::Result {
+        self.write_message(f)?;
+        if let Some(location) = self.location() {
+            write!(f, " (at {location})")?;
+        }
+        Ok(())
+    }
+
+    /// Errors that are not a `Diagnostic` are assumed to display only their own message.
+    fn write_message(self, f: &mut dyn Write) -> fmt::Result {
+        match self.diagnostic() {
+            Some(diagnostic) => diagnostic.write_message(f),
+            None => write!(f, "{}", self.error),
+        }
+    }
+
+    fn diagnostic(self) -> Option<&'a (dyn Diagnostic + 'a)> {
+        self.diagnostic.or_else(|| self.provided())
+    }
+
+    fn lines(self, kind: Kind) -> Option<Lines<'a>> {
+        let diagnostic = self.diagnostic()?;
+        match kind {
+            Kind::Help => diagnostic.help().map(|help| help.lines),
+            Kind::Note => diagnostic.note().map(|note| note.lines),
+        }
+    }
+
+    fn location(self) -> Option<&'a Location<'static>> {
+        match self.diagnostic() {
+            Some(diagnostic) => diagnostic.location(),
+            None => self.provided(),
+        }
+    }
+
+    fn code(self) -> Option<Code> {
+        match self.diagnostic() {
+            Some(diagnostic) => diagnostic.code(),
+            None => self.provided_value(),
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    fn trace(self) -> Option<&'a Trace> {
+        match self.diagnostic() {
+            Some(diagnostic) => diagnostic.trace(),
+            None => self.provided(),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn backtrace(self) -> Option<&'a std::backtrace::Backtrace> {
+        match self.diagnostic() {
+            Some(diagnostic) => diagnostic.backtrace(),
+            None => self.provided(),
+        }
+    }
+
+    /// What a source that is not a `Diagnostic` provides through `Error::provide`.
+    #[cfg(feature = "nightly")]
+    fn provided<T: ?Sized + 'static>(self) -> Option<&'a T> {
+        core::error::request_ref::<T>(self.error)
+    }
+
+    #[cfg(not(feature = "nightly"))]
+    fn provided<T: ?Sized + 'static>(self) -> Option<&'a T> {
+        None
+    }
+
+    #[cfg(feature = "nightly")]
+    fn provided_value<T: 'static>(self) -> Option<T> {
+        core::error::request_value::<T>(self.error)
+    }
+
+    #[cfg(not(feature = "nightly"))]
+    fn provided_value<T: 'static>(self) -> Option<T> {
+        None
+    }
+}
+
+/// Writes to the formatter, indenting every line but the first.
+struct Indented<'a, 'b> {
+    f: &'a mut fmt::Formatter<'b>,
+    indent: &'a str,
+    depth: usize,
+    newline: bool,
+}
+
+impl<'a, 'b> Indented<'a, 'b> {
+    fn new(f: &'a mut fmt::Formatter<'b>, indent: &'a str) -> Self {
+        Self {
+            f,
+            indent,
+            depth: 1,
+            newline: false,
+        }
+    }
+
+    /// Repeats the indent `depth` times.
+    fn depth(mut self, depth: usize) -> Self {
+        self.depth = depth;
+        self
+    }
+}
+
+impl Write for Indented<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (i, line) in s.split('\n').enumerate() {
+            if i > 0 {
+                self.f.write_char('\n')?;
+                self.newline = true;
+            }
+            if self.newline && !line.is_empty() {
+                for _ in 0..self.depth {
+                    self.f.write_str(self.indent)?;
+                }
+                self.newline = false;
+            }
+            self.f.write_str(line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the separator before the first thing written, if anything is.
+struct Separated<'a> {
+    f: &'a mut dyn Write,
+    separator: Option<&'a str>,
+}
+
+impl Write for Separated<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if s.is_empty() {
+            return Ok(());
+        }
+        if let Some(separator) = self.separator.take() {
+            self.f.write_str(separator)?;
+        }
+        self.f.write_str(s)
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+    use alloc::format;
+    use alloc::string::{String, ToString};
+    use alloc::vec::Vec;
+
+    #[derive(Debug)]
+    struct Leaf;
+
+    impl fmt::Display for Leaf {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("leaf\nsecond line")
+        }
+    }
+
+    impl Error for Leaf {
+        #[cfg(feature = "nightly")]
+        fn provide<'a>(&'a self, request: &mut core::error::Request<'a>) {
+            request.provide_ref::<dyn Diagnostic>(self);
+        }
+    }
+
+    impl Diagnostic for Leaf {
+        fn write_help_line(&self, _: usize, f: &mut dyn Write) -> fmt::Result {
+            f.write_str("try again")
+        }
+
+        fn help(&self) -> Option<Help<'_>> {
+            Some(Help::new(self, "Leaf", 1))
+        }
+    }
+
+    #[derive(Debug)]
+    struct Mid {
+        source: Leaf,
+        location: &'static Location<'static>,
+    }
+
+    impl fmt::Display for Mid {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("mid")
+        }
+    }
+
+    impl Error for Mid {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(&self.source)
+        }
+
+        #[cfg(feature = "nightly")]
+        fn provide<'a>(&'a self, request: &mut core::error::Request<'a>) {
+            request.provide_ref::<dyn Diagnostic>(self);
+            request.provide_ref::<Location<'static>>(self.location);
+        }
+    }
+
+    impl Diagnostic for Mid {
+        fn write_help_line(&self, index: usize, f: &mut dyn Write) -> fmt::Result {
+            match index {
+                0 => {
+                    f.write_str("try ")?;
+                    f.write_str("again")
+                }
+                _ => f.write_str("check the middle"),
+            }
+        }
+
+        fn help(&self) -> Option<Help<'_>> {
+            Some(Help::new(self, "Mid", 2))
+        }
+
+        fn location(&self) -> Option<&'static Location<'static>> {
+            Some(self.location)
+        }
+
+        fn diagnostic_source(&self) -> Option<&dyn Diagnostic> {
+            Some(&self.source)
+        }
+    }
+
+    #[derive(Debug)]
+    struct Top {
+        source: Mid,
+    }
+
+    impl fmt::Display for Top {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("top")
+        }
+    }
+
+    impl Error for Top {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(&self.source)
+        }
+    }
+
+    impl Diagnostic for Top {
+        fn diagnostic_source(&self) -> Option<&dyn Diagnostic> {
+            Some(&self.source)
+        }
+    }
+
+    fn error() -> (Top, &'static Location<'static>) {
+        let location = Location::caller();
+        let error = Top {
+            source: Mid {
+                source: Leaf,
+                location,
+            },
+        };
+        (error, location)
+    }
+
+    fn report(style: Style) -> (String, &'static Location<'static>) {
+        let (error, location) = error();
+        (Report::new(error).style(style).to_string(), location)
+    }
+
+    #[test]
+    fn single_line() {
+        let (report, location) = report(Style::SingleLine);
+        assert_eq!(
+            report,
+            format!("top: mid (at {location}): leaf\nsecond line")
+        );
+    }
+
+    #[test]
+    fn borrowed() {
+        let location = Location::caller();
+        let error = Top {
+            source: Mid {
+                source: Leaf,
+                location,
+            },
+        };
+        let report = Report::new(&error).style(Style::SingleLine).to_string();
+        assert_eq!(
+            report,
+            format!("top: mid (at {location}): leaf\nsecond line")
+        );
+    }
+
+    #[test]
+    fn multi_line() {
+        let (report, location) = report(Style::MultiLine);
+        assert_eq!(
+            report,
+            format!(
+                "top\n\nCaused by:\n    1: mid (at {location})\n    2: leaf\n       second line\n\nHelp: try again\nHelp: check the middle\n"
+            )
+        );
+    }
+
+    #[test]
+    fn tree() {
+        let (report, location) = report(Style::Tree);
+        assert_eq!(
+            report,
+            format!(
+                "top\n└─ mid (at {location})\n   └─ leaf\n      second line\n\nHelp: try again\nHelp: check the middle\n"
+            )
+        );
+    }
+
+    #[test]
+    fn help_order() {
+        let (error, _) = error();
+        let report = Report::new(error).order(Order::InnermostFirst);
+        let help: Vec<_> = report
+            .help()
+            .map(|line| (line.origin(), line.index(), line.to_string()))
+            .collect();
+        assert_eq!(
+            help,
+            [
+                ("Leaf", 0, String::from("try again")),
+                ("Mid", 1, String::from("check the middle")),
+            ]
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn backtrace() {
+        use std::backtrace::Backtrace;
+
+        #[derive(Debug)]
+        struct Traced(Backtrace);
+
+        impl fmt::Display for Traced {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("traced")
+            }
+        }
+
+        impl Error for Traced {
+            #[cfg(feature = "nightly")]
+            fn provide<'a>(&'a self, request: &mut core::error::Request<'a>) {
+                request.provide_ref::<Backtrace>(&self.0);
+            }
+        }
+
+        impl Diagnostic for Traced {
+            fn backtrace(&self) -> Option<&Backtrace> {
+                Some(&self.0)
+            }
+        }
+
+        let report = Report::new(Traced(Backtrace::force_capture())).to_string();
+        assert!(
+            report.starts_with("traced\n\nStack backtrace:\n"),
+            "{report}"
+        );
+        let report = Report::new(Traced(Backtrace::disabled())).to_string();
+        assert_eq!(report, "traced");
+    }
+
+    #[test]
+    fn ok_or_ctx_location() {
+        struct Here;
+
+        impl With<Option<Infallible>, &'static Location<'static>> for Here {
+            fn bind(self, _: Option<Infallible>) -> &'static Location<'static> {
+                Location::caller()
+            }
+        }
+
+        let line = line!();
+        let location = None::<()>.ok_or_ctx(Here).unwrap_err();
+        assert_eq!(location.line(), line + 1);
+        assert_eq!(Some(1).ok_or_else_ctx(|| Here), Ok(1));
+    }
+
+    #[test]
+    fn same_text_pieces() {
+        assert!(same_text(&format_args!("a{}c", "b"), &"abc"));
+        assert!(same_text(&"", &""));
+        assert!(!same_text(&"ab", &"abc"));
+        assert!(!same_text(&format_args!("ab{}", "c"), &"ab"));
+        assert!(!same_text(&"abd", &format_args!("{}{}", "ab", "c")));
     }
 }
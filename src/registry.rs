@@ -0,0 +1,198 @@
+//! Every type that derives `Error`, collected at link time, for the `registry` feature.
+//!
+//! Everything here is taken from the attributes as written, so messages and help are templates
+//! like `failed to load {name}` rather than formatted text.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+/// A type that derives `Error`.
+#[derive(Debug)]
+pub struct ErrorType {
+    pub name: &'static str,
+    /// The module the type is defined in, as given by `module_path!`
+    pub module: &'static str,
+    /// One variant without a name for structs
+    pub variants: &'static [Variant],
+}
+
+#[derive(Debug)]
+pub struct Variant {
+    /// `None` for structs
+    pub name: Option<&'static str>,
+    pub code: Option<&'static str>,
+    /// `None` for `#[error(transparent)]`
+    pub message: Option<&'static str>,
+    pub help: &'static [&'static str],
+    pub notes: &'static [&'static str],
+    pub fields: &'static [Field],
+}
+
+#[derive(Debug)]
+pub struct Field {
+    /// The name of the field, or its index in a tuple variant
+    pub name: &'static str,
+    pub ty: &'static str,
+}
+
+/// Every registered type, in no particular order.
+pub fn all() -> impl Iterator<Item = &'static ErrorType> {
+    crate::__private::REGISTRY.iter()
+}
+
+/// Every registered type, sorted by path so that the output is stable.
+fn sorted() -> Vec<&'static ErrorType> {
+    let mut types: Vec<_> = all().collect();
+    types.sort_by_key(|ty| (ty.module, ty.name));
+    types
+}
+
+/// A Markdown catalogue of every registered type.
+pub fn to_markdown() -> String {
+    let mut out = String::from("# Errors\n");
+    for ty in sorted() {
+        // Writing to a `String` can not fail
+        let _ = write_type(&mut out, ty);
+    }
+    out
+}
+
+fn write_type(out: &mut String, ty: &ErrorType) -> core::fmt::Result {
+    write!(out, "\n## `{}::{}`\n", ty.module, ty.name)?;
+    for variant in ty.variants {
+        if let Some(name) = variant.name {
+            write!(out, "\n### `{name}`\n")?;
+        }
+        if let Some(code) = variant.code {
+            write!(out, "\nCode: `{code}`\n")?;
+        }
+        match variant.message {
+            Some(message) => write!(out, "\n`{message}`\n")?,
+            None => out.push_str("\nForwards to its source.\n"),
+        }
+
+        if !variant.help.is_empty() || !variant.notes.is_empty() {
+            out.push('\n');
+        }
+        for help in variant.help {
+            writeln!(out, "- Help: `{help}`")?;
+        }
+        for note in variant.notes {
+            writeln!(out, "- Note: `{note}`")?;
+        }
+
+        if !variant.fields.is_empty() {
+            out.push_str("\n| Field | Type |\n| --- | --- |\n");
+            for field in variant.fields {
+                writeln!(out, "| `{}` | `{}` |", field.name, field.ty)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A JSON array of every registered type.
+#[cfg(feature = "serde")]
+pub fn to_json() -> Result<String, serde_json::Error> {
+    serde_json::to_string(&sorted())
+}
+
+#[cfg(feature = "serde")]
+mod ser {
+    use super::{ErrorType, Field, Variant};
+    use serde::ser::{Serialize, SerializeMap, Serializer};
+
+    impl Serialize for ErrorType {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut map = serializer.serialize_map(Some(3))?;
+            map.serialize_entry("name", self.name)?;
+            map.serialize_entry("module", self.module)?;
+            map.serialize_entry("variants", self.variants)?;
+            map.end()
+        }
+    }
+
+    impl Serialize for Variant {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut map = serializer.serialize_map(Some(6))?;
+            map.serialize_entry("name", &self.name)?;
+            map.serialize_entry("code", &self.code)?;
+            map.serialize_entry("message", &self.message)?;
+            map.serialize_entry("help", self.help)?;
+            map.serialize_entry("notes", self.notes)?;
+            map.serialize_entry("fields", self.fields)?;
+            map.end()
+        }
+    }
+
+    impl Serialize for Field {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut map = serializer.serialize_map(Some(2))?;
+            map.serialize_entry("name", self.name)?;
+            map.serialize_entry("ty", self.ty)?;
+            map.end()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[linkme::distributed_slice(crate::__private::REGISTRY)]
+    static LOAD: ErrorType = ErrorType {
+        name: "LoadError",
+        module: "app::load",
+        variants: &[
+            Variant {
+                name: Some("Missing"),
+                code: Some("E0001"),
+                message: Some("missing {name}"),
+                help: &["check {name}"],
+                notes: &[],
+                fields: &[Field {
+                    name: "name",
+                    ty: "String",
+                }],
+            },
+            Variant {
+                name: Some("Io"),
+                code: Some("E0002"),
+                message: None,
+                help: &[],
+                notes: &[],
+                fields: &[],
+            },
+        ],
+    };
+
+    #[test]
+    fn markdown() {
+        assert_eq!(
+            to_markdown(),
+            "# Errors
+
+## `app::load::LoadError`
+
+### `Missing`
+
+Code: `E0001`
+
+`missing {name}`
+
+- Help: `check {name}`
+
+| Field | Type |
+| --- | --- |
+| `name` | `String` |
+
+### `Io`
+
+Code: `E0002`
+
+Forwards to its source.
+"
+        );
+    }
+}
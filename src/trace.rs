@@ -0,0 +1,127 @@
+//! The places an error was propagated through, for `#[trace]` fields and [`Context::trace`].
+//!
+//! [`Context::trace`]: crate::Context::trace
+
+use crate::Diagnostic;
+use alloc::vec::Vec;
+use core::convert::Infallible;
+use core::fmt;
+use core::panic::Location;
+
+/// Every call site an error passed through, oldest first.
+///
+/// A `#[trace]` field starts with where the error was created, and every
+/// [`Context::trace`](crate::Context::trace) on the way up adds one more. Displays as
+/// `a.rs:10, b.rs:44`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Trace {
+    locations: Vec<&'static Location<'static>>,
+}
+
+impl Trace {
+    /// A trace that starts at the caller.
+    #[track_caller]
+    pub fn capture() -> Self {
+        Self::from(Location::caller())
+    }
+
+    pub fn push(&mut self, location: &'static Location<'static>) {
+        self.locations.push(location);
+    }
+
+    pub fn locations(&self) -> &[&'static Location<'static>] {
+        &self.locations
+    }
+}
+
+/// A trace that starts at `location`.
+impl From<&'static Location<'static>> for Trace {
+    fn from(location: &'static Location<'static>) -> Self {
+        Self {
+            locations: alloc::vec![location],
+        }
+    }
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, location) in self.locations.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}:{}", location.file(), location.line())?;
+        }
+        Ok(())
+    }
+}
+
+/// The error half of a `Result` that [`Context::trace`](crate::Context::trace) can record in.
+pub trait Traced {
+    fn record(&mut self, location: &'static Location<'static>);
+}
+
+impl<E: Diagnostic> Traced for Result<Infallible, E> {
+    fn record(&mut self, location: &'static Location<'static>) {
+        if let Some(trace) = self.as_mut().err().and_then(|error| error.trace_mut()) {
+            trace.push(location);
+        }
+    }
+}
+
+/// A `None` has nowhere to record the location, so this does nothing.
+impl Traced for Option<Infallible> {
+    fn record(&mut self, _: &'static Location<'static>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Context, Report};
+    use alloc::format;
+    use alloc::string::ToString;
+    use core::error::Error;
+
+    #[derive(Debug)]
+    struct Leaf(Trace);
+
+    impl fmt::Display for Leaf {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("leaf")
+        }
+    }
+
+    impl Error for Leaf {
+        #[cfg(feature = "nightly")]
+        fn provide<'a>(&'a self, request: &mut core::error::Request<'a>) {
+            request.provide_ref::<Trace>(&self.0);
+        }
+    }
+
+    impl Diagnostic for Leaf {
+        fn trace(&self) -> Option<&Trace> {
+            Some(&self.0)
+        }
+
+        fn trace_mut(&mut self) -> Option<&mut Trace> {
+            Some(&mut self.0)
+        }
+    }
+
+    #[test]
+    fn propagated() {
+        let line = line!();
+        let result: Result<(), Leaf> = Err(Leaf(Trace::capture()));
+        let error = result.trace().unwrap_err();
+        let lines: Vec<_> = error.0.locations().iter().map(|l| l.line()).collect();
+        assert_eq!(lines, [line + 1, line + 2]);
+        assert_eq!(
+            Report::new(error).to_string(),
+            format!(
+                "leaf\n\nPropagated through: {file}:{}, {file}:{}\n",
+                line + 1,
+                line + 2,
+                file = file!(),
+            )
+        );
+    }
+}
@@ -0,0 +1,758 @@
+//! Errors made with `#[derive(Error)]`, and what they display.
+
+#![cfg(feature = "std")]
+#![cfg_attr(feature = "nightly", feature(error_generic_member_access))]
+
+use error::{Context, Error};
+use std::panic::Location;
+
+#[derive(Error)]
+enum Leaf {
+    #[error = "io failed"]
+    Io { source: std::io::Error },
+}
+
+#[derive(Error)]
+enum Mid {
+    #[error = "cannot load {name}"]
+    Load {
+        name: String,
+        source: Leaf,
+        #[location]
+        location: &'static Location<'static>,
+    },
+}
+
+#[derive(Error)]
+#[top_level]
+enum Top {
+    #[error = "top failed"]
+    #[help = "check the disk"]
+    Run { source: Mid },
+}
+
+#[test]
+fn top_level_chain() {
+    let line = line!();
+    let leaf: Result<(), Leaf> = Err(std::io::Error::other("boom")).context(Io);
+    let mid = leaf.context(Load {
+        name: "config".to_string(),
+    });
+    let top = mid.context(Run).unwrap_err();
+    let Top::Run {
+        source: Mid::Load { location, .. },
+    } = &top;
+    assert_eq!(location.line(), line + 2);
+    assert_eq!(
+        top.to_string(),
+        format!(
+            "top failed\n\
+             Caused by: cannot load config (at {location})\n\
+             Caused by: io failed\n\
+             Caused by: boom\n\
+             \n\
+             Help: check the disk\n",
+        )
+    );
+}
+
+#[derive(Error)]
+#[top_level]
+enum Shutdown {
+    #[error = "shutdown failed"]
+    #[code = "S1"]
+    #[help = "check the disk"]
+    Halt { source: Leaf },
+}
+
+#[test]
+fn top_level_report() {
+    let leaf: Result<(), Leaf> = Err(std::io::Error::other("boom")).context(Io);
+    let halt = leaf.context(Halt).unwrap_err();
+    assert_eq!(
+        halt.to_string(),
+        "error[S1]: shutdown failed\n\
+         Caused by: io failed\n\
+         Caused by: boom\n\
+         \n\
+         Help: check the disk\n"
+    );
+    // A report prints the code, the chain and the help once, and takes only the message from it
+    assert_eq!(
+        error::Report::new(&halt).to_string(),
+        "error[S1]: shutdown failed\n\
+         \n\
+         Caused by:\n    \
+         1: io failed\n    \
+         2: boom\n\
+         \n\
+         Help: check the disk\n"
+    );
+    #[cfg(feature = "serde")]
+    assert!(error::to_json(&halt)
+        .unwrap()
+        .contains(r#""message":"shutdown failed","#));
+}
+
+#[derive(Error)]
+enum Generic<T> {
+    #[error = "serialized"]
+    Serialized {
+        #[serialize]
+        _value: T,
+    },
+    #[error = "caused"]
+    Caused { source: T },
+}
+
+struct Plain;
+
+#[derive(Error)]
+#[top_level]
+#[error = "wrapper"]
+struct Wrapper<E> {
+    source: E,
+}
+
+#[test]
+fn display_bounds() {
+    let serialized = Generic::Serialized { _value: Plain };
+    let caused = Generic::Caused { source: Plain };
+    assert_eq!(serialized.to_string(), "serialized");
+    assert_eq!(caused.to_string(), "caused");
+
+    // The chain of a top level error does need its source to be one
+    let wrapper: Result<(), Wrapper<std::io::Error>> =
+        Err(std::io::Error::other("inner")).context(WrapperContext);
+    let wrapper = wrapper.unwrap_err();
+    assert_eq!(wrapper.to_string(), "wrapper\nCaused by: inner\n");
+    assert_eq!(format!("{wrapper:?}"), wrapper.to_string());
+}
+
+#[derive(Error)]
+#[error = "could not read {path}"]
+#[help = "is {path} readable?"]
+struct ReadFailed {
+    path: String,
+    #[source]
+    source: std::io::Error,
+    #[location]
+    location: &'static Location<'static>,
+}
+
+#[derive(Error)]
+#[error = "busy"]
+struct Busy;
+
+#[test]
+fn structs() {
+    use std::error::Error as _;
+
+    let line = line!();
+    let read = Err::<(), _>(std::io::Error::other("denied"))
+        .context(ReadFailedContext {
+            path: "a.txt".to_string(),
+        })
+        .unwrap_err();
+    let location = read.location;
+    assert_eq!(location.line(), line + 2);
+    assert_eq!(read.to_string(), "could not read a.txt");
+    assert_eq!(read.source().unwrap().to_string(), "denied");
+    let help = error::Diagnostic::help(&read).unwrap();
+    assert_eq!(help.to_string(), "Help: is a.txt readable?\n");
+
+    let busy: Result<(), Busy> = None.context(BusyContext);
+    assert_eq!(busy.unwrap_err().to_string(), "busy");
+}
+
+#[derive(Error)]
+enum Value {
+    #[error = "bad value {0} for {1:?}"]
+    BadValue(u32, String),
+    #[error = "cannot decode"]
+    Decode(#[source] std::io::Error),
+}
+
+#[derive(Error)]
+#[error = "at line {0}"]
+struct AtLine(u32, #[source] Value);
+
+#[test]
+fn tuples() {
+    use std::error::Error as _;
+
+    assert_eq!(
+        BadValue::new(3, "x".to_string()).to_string(),
+        "bad value 3 for \"x\""
+    );
+    let decode: Result<(), Value> = Err(std::io::Error::other("eof")).context(Decode);
+    let at_line = decode.context(AtLineContext(7)).unwrap_err();
+    assert_eq!(at_line.to_string(), "at line 7");
+    let source = at_line.source().unwrap();
+    assert_eq!(source.to_string(), "cannot decode");
+    assert_eq!(source.source().unwrap().to_string(), "eof");
+}
+
+#[derive(Error)]
+enum Syntax<'a, T>
+where
+    T: Clone + 'static,
+{
+    #[error = "unexpected {token:?} in {input}"]
+    #[help = "expected {expected}"]
+    Unexpected {
+        input: &'a str,
+        token: char,
+        expected: T,
+    },
+    #[error = "nested"]
+    Nested {
+        #[source]
+        source: Box<Syntax<'static, T>>,
+    },
+}
+
+#[test]
+fn generics() {
+    use std::error::Error as _;
+
+    let unexpected: Syntax<'_, u8> = Unexpected::new("1 + x", 'x', 3u8);
+    assert_eq!(unexpected.to_string(), "unexpected 'x' in 1 + x");
+    let help = error::Diagnostic::help(&unexpected).unwrap();
+    assert_eq!(help.to_string(), "Help: expected 3\n");
+    // Generic errors cannot provide themselves, but a report still has their help
+    assert_eq!(
+        error::Report::new(&unexpected).to_string(),
+        "unexpected 'x' in 1 + x\n\nHelp: expected 3\n"
+    );
+
+    // `Nested` has no fields, so its selector has none of the enum's generics
+    let inner: Result<(), Box<Syntax<'static, &str>>> =
+        Err(Box::new(Unexpected::new("(", ')', "an expression")));
+    let nested: Syntax<'_, &str> = inner.context(Nested).unwrap_err();
+    assert_eq!(nested.to_string(), "nested");
+    assert_eq!(nested.source().unwrap().to_string(), "unexpected ')' in (");
+}
+
+#[derive(Error)]
+enum Open {
+    #[error = "cannot find {name}"]
+    NotFound {
+        name: String,
+        #[source]
+        cause: std::io::Error,
+        #[location]
+        at: &'static Location<'static>,
+    },
+    #[error = "corrupt"]
+    Corrupt { source: Value },
+}
+
+#[test]
+fn source_fields() {
+    use std::error::Error as _;
+
+    let line = line!();
+    let result: Result<(), Open> = Err(std::io::Error::other("gone")).context(NotFound {
+        name: "a".to_string(),
+    });
+    let not_found = result.unwrap_err();
+    let Open::NotFound { at, .. } = &not_found else {
+        unreachable!();
+    };
+    assert_eq!(at.line(), line + 1);
+    assert_eq!(not_found.to_string(), "cannot find a");
+
+    let corrupt: Result<(), Open> = Err(BadValue::new(1, "y".to_string())).context(Corrupt);
+    let corrupt = corrupt.unwrap_err();
+    assert_eq!(
+        corrupt.source().unwrap().to_string(),
+        "bad value 1 for \"y\""
+    );
+}
+
+#[derive(Error)]
+enum Startup {
+    #[error = "cannot open config"]
+    Config(
+        #[from] std::io::Error,
+        #[location] &'static Location<'static>,
+    ),
+    #[error = "invalid config"]
+    Invalid {
+        #[from]
+        value: Value,
+    },
+}
+
+#[test]
+fn from() {
+    use std::error::Error as _;
+
+    let line = line!();
+    let result = (|| -> Result<(), Startup> {
+        Err(std::io::Error::other("gone"))?;
+        Ok(())
+    })();
+    let Err(Startup::Config(io, location)) = &result else {
+        panic!("expected `Config`");
+    };
+    assert_eq!(io.to_string(), "gone");
+    assert_eq!((location.file(), location.line()), (file!(), line + 2));
+
+    let invalid = Startup::from(BadValue::new(1, "y".to_string()));
+    assert_eq!(invalid.to_string(), "invalid config");
+    assert_eq!(
+        invalid.source().unwrap().to_string(),
+        "bad value 1 for \"y\""
+    );
+}
+
+#[derive(Error)]
+enum Service {
+    #[error(transparent)]
+    Read(#[from] ReadFailed),
+    #[error(transparent)]
+    Store { inner: std::io::Error },
+}
+
+#[test]
+fn transparent() {
+    use std::error::Error as _;
+
+    let read: Result<(), ReadFailed> =
+        Err(std::io::Error::other("denied")).context(ReadFailedContext {
+            path: "b.txt".to_string(),
+        });
+    let service = Service::from(read.unwrap_err());
+    assert_eq!(service.to_string(), "could not read b.txt");
+    assert_eq!(service.source().unwrap().to_string(), "denied");
+    let help = error::Diagnostic::help(&service).unwrap();
+    assert_eq!(help.to_string(), "Help: is b.txt readable?\n");
+
+    let store: Result<(), Service> = Err(std::io::Error::other("full")).context(Store);
+    let store = store.unwrap_err();
+    assert_eq!(store.to_string(), "full");
+    assert!(store.source().is_none());
+}
+
+#[derive(Error)]
+enum Never {}
+
+#[test]
+fn no_variants() {
+    fn error<E: std::error::Error>() {}
+    error::<Never>();
+}
+
+#[derive(Error)]
+#[debug(structural)]
+enum Job<T> {
+    #[error = "job {id} failed"]
+    Failed {
+        id: T,
+        source: Value,
+        #[location]
+        location: &'static Location<'static>,
+    },
+    #[error = "pair {0} {1}"]
+    Pair(u8, String),
+    #[error = "idle"]
+    Idle,
+}
+
+#[test]
+fn structural_debug() {
+    assert_eq!(format!("{:?}", Job::<u8>::Idle), "Idle");
+    assert_eq!(
+        format!("{:?}", Pair::new::<u8>(1, "s".to_string())),
+        "Pair(1, \"s\")"
+    );
+
+    let value: Result<(), Value> = Err(BadValue::new(2, "z".to_string()));
+    let failed: Job<u8> = value.context(Failed { id: 5u8 }).unwrap_err();
+    let Job::Failed { location, .. } = &failed else {
+        unreachable!();
+    };
+    assert_eq!(
+        format!("{failed:?}"),
+        format!("Failed {{ id: 5, source: bad value 2 for \"z\", location: {location} }}")
+    );
+}
+
+#[derive(Error)]
+enum Lookup {
+    #[error = "not found"]
+    #[code = "L1"]
+    Missing,
+}
+
+#[derive(Error)]
+#[code_prefix = "E"]
+enum Command {
+    #[error = "parse failed"]
+    #[code = "01"]
+    Parse,
+    #[error(transparent)]
+    Forward(Lookup),
+}
+
+#[test]
+fn codes() {
+    use error::{Code, Diagnostic, Report};
+
+    let parse = Command::Parse;
+    let forward = Command::Forward(Lookup::Missing);
+    assert_eq!(parse.code(), Some("E01"));
+    assert_eq!(forward.code(), Some("L1"));
+    assert_eq!(Diagnostic::code(&forward), Some(Code::new("L1")));
+    assert_eq!(Report::new(parse).to_string(), "error[E01]: parse failed");
+    assert_eq!(Report::new(forward).to_string(), "error[L1]: not found");
+}
+
+#[derive(Error)]
+enum Fetch {
+    #[error = "fetch failed"]
+    #[help = "check the url"]
+    Unreachable,
+}
+
+#[derive(Error)]
+#[help = "run with --verbose"]
+enum Client {
+    #[error = "timed out"]
+    Timeout,
+    #[error(transparent)]
+    Forwarded(Fetch),
+}
+
+#[test]
+fn default_help() {
+    use error::Report;
+
+    assert_eq!(
+        Report::new(Client::Timeout).to_string(),
+        "timed out\n\nHelp: run with --verbose\n"
+    );
+    assert_eq!(
+        Report::new(Client::Forwarded(Fetch::Unreachable)).to_string(),
+        "fetch failed\n\nHelp: check the url\n"
+    );
+}
+
+#[derive(Error)]
+enum Transfer {
+    #[error = "pull failed"]
+    Pull { source: std::io::Error },
+    #[error = "push to {remote} failed"]
+    #[clone]
+    Push {
+        remote: String,
+        source: std::io::Error,
+    },
+}
+
+#[cfg(feature = "futures")]
+#[test]
+fn selectors_in_futures() {
+    use error::{FutureContext, StreamContext};
+    use futures_core::Stream;
+    use std::future::Future;
+    use std::pin::{pin, Pin};
+    use std::task::{Context as TaskContext, Poll, Waker};
+
+    struct Failing(usize);
+
+    impl Stream for Failing {
+        type Item = Result<(), std::io::Error>;
+
+        fn poll_next(
+            mut self: Pin<&mut Self>,
+            _: &mut TaskContext<'_>,
+        ) -> Poll<Option<Self::Item>> {
+            let next = self.0.checked_sub(1);
+            Poll::Ready(next.map(|n| {
+                self.0 = n;
+                Err(std::io::Error::other("down"))
+            }))
+        }
+    }
+
+    let cx = &mut TaskContext::from_waker(Waker::noop());
+    let future =
+        pin!(std::future::ready(Err::<(), _>(std::io::Error::other("down"))).context(Pull));
+    let Poll::Ready(Err(pull)) = future.poll(cx) else {
+        unreachable!();
+    };
+    assert_eq!(pull.to_string(), "pull failed");
+
+    let mut pulls = pin!(Failing(2).context(Pull));
+    let mut pushes = pin!(Failing(2).context(Push {
+        remote: "origin".to_string(),
+    }));
+    for _ in 0..2 {
+        let Poll::Ready(Some(Err(pull))) = pulls.as_mut().poll_next(cx) else {
+            unreachable!();
+        };
+        assert_eq!(pull.to_string(), "pull failed");
+        let Poll::Ready(Some(Err(push))) = pushes.as_mut().poll_next(cx) else {
+            unreachable!();
+        };
+        assert_eq!(push.to_string(), "push to origin failed");
+    }
+}
+
+#[derive(Debug, Default)]
+struct Empty;
+
+impl std::fmt::Display for Empty {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("empty")
+    }
+}
+
+impl std::error::Error for Empty {}
+
+#[derive(Error)]
+enum Find {
+    #[error = "no user {id}"]
+    NoUser {
+        id: u32,
+        #[from_none]
+        source: error::NoneError,
+    },
+    #[error = "no group"]
+    NoGroup {
+        #[from_none]
+        cause: Empty,
+    },
+}
+
+#[test]
+fn from_none() {
+    use error::OptionExt;
+    use std::error::Error as _;
+
+    let user = None::<u8>.context(NoUser { id: 3 }).unwrap_err();
+    assert_eq!(user.to_string(), "no user 3");
+    assert_eq!(user.source().unwrap().to_string(), "the value was `None`");
+    let group = None::<u8>.ok_or_ctx(NoGroup).unwrap_err();
+    assert_eq!(group.source().unwrap().to_string(), "empty");
+
+    // The selector still takes the source from a `Result`
+    let group: Result<(), Empty> = Err(Empty);
+    assert!(matches!(group.context(NoGroup), Err(Find::NoGroup { .. })));
+}
+
+#[derive(Error)]
+enum Disk {
+    #[error = "disk full"]
+    #[note = "{free} bytes left"]
+    Full { free: u64 },
+}
+
+#[derive(Error)]
+#[top_level]
+enum Save {
+    #[error = "cannot save"]
+    #[help = "free some space"]
+    #[note = "saves go to the data directory"]
+    Write { source: Disk },
+}
+
+#[test]
+fn notes() {
+    let full: Result<(), Disk> = Err(Full::new(12));
+    let save = full.context(Write).unwrap_err();
+    assert_eq!(
+        save.to_string(),
+        "cannot save\n\
+         Caused by: disk full\n\
+         \n\
+         Help: free some space\n\
+         Note: saves go to the data directory\n\
+         Note: 12 bytes left\n"
+    );
+    assert_eq!(
+        error::Report::new(&save).to_string(),
+        "cannot save\n\
+         \n\
+         Caused by:\n    \
+         1: disk full\n\
+         \n\
+         Help: free some space\n\
+         Note: saves go to the data directory\n\
+         Note: 12 bytes left\n"
+    );
+}
+
+#[derive(Debug, Error)]
+#[debug(none)]
+enum Parsed<T> {
+    #[error = "cannot parse {input}"]
+    Malformed { input: T },
+}
+
+#[test]
+fn own_debug() {
+    // The bounds of `#[derive(Debug)]` are enough for the `Error` impl
+    let malformed: Parsed<&str> = Malformed::new("x");
+    assert_eq!(format!("{malformed:?}"), "Malformed { input: \"x\" }");
+    assert_eq!(malformed.to_string(), "cannot parse x");
+    let report = error::Report::new(&malformed);
+    assert_eq!(report.to_string(), "cannot parse x");
+}
+
+#[cfg(feature = "registry")]
+#[test]
+fn registry() {
+    let find = |name: &str| {
+        error::registry::all()
+            .find(|ty| ty.name == name && ty.module == module_path!())
+            .unwrap()
+    };
+
+    // A struct has one variant without a name, with the field types as written
+    let read_failed = find("ReadFailed");
+    let [variant] = read_failed.variants else {
+        panic!("{read_failed:?}");
+    };
+    assert_eq!(variant.name, None);
+    assert_eq!(variant.code, None);
+    assert_eq!(variant.message, Some("could not read {path}"));
+    assert_eq!(variant.help, ["is {path} readable?"]);
+    let fields: Vec<_> = variant.fields.iter().map(|f| (f.name, f.ty)).collect();
+    assert_eq!(
+        fields,
+        [
+            ("path", "String"),
+            ("source", "std::io::Error"),
+            ("location", "&'static Location<'static>"),
+        ]
+    );
+
+    // Codes have the prefix, and transparent variants have no message
+    let command = find("Command");
+    let variants: Vec<_> = command
+        .variants
+        .iter()
+        .map(|v| (v.name, v.code, v.message))
+        .collect();
+    assert_eq!(
+        variants,
+        [
+            (Some("Parse"), Some("E01"), Some("parse failed")),
+            (Some("Forward"), None, None),
+        ]
+    );
+    assert_eq!(command.variants[1].fields[0].name, "0");
+
+    assert_eq!(find("Disk").variants[0].notes, ["{free} bytes left"]);
+}
+
+#[derive(Error)]
+enum Crash {
+    #[error = "worker crashed"]
+    Worker {
+        #[from]
+        source: std::io::Error,
+        #[backtrace]
+        backtrace: std::backtrace::Backtrace,
+    },
+}
+
+#[test]
+fn backtraces() {
+    use error::Diagnostic;
+    use std::backtrace::BacktraceStatus;
+
+    for crash in [
+        Crash::from(std::io::Error::other("boom")),
+        Err::<(), _>(std::io::Error::other("boom"))
+            .context(Worker)
+            .unwrap_err(),
+    ] {
+        let backtrace = Diagnostic::backtrace(&crash).unwrap();
+        #[cfg(feature = "nightly")]
+        assert!(std::ptr::eq(
+            std::error::request_ref::<std::backtrace::Backtrace>(&crash).unwrap(),
+            backtrace
+        ));
+        // Whether it has frames depends on `RUST_BACKTRACE`, and so does the report
+        let report = error::Report::new(&crash).to_string();
+        assert_eq!(
+            report.contains("Stack backtrace:\n"),
+            backtrace.status() == BacktraceStatus::Captured,
+            "{report}"
+        );
+    }
+}
+
+#[derive(Error)]
+enum Upload {
+    #[error = "upload stalled"]
+    Stalled {
+        #[from]
+        source: std::io::Error,
+        #[trace]
+        trace: error::Trace,
+    },
+}
+
+#[derive(Error)]
+enum Deploy {
+    #[error = "deploy failed"]
+    Publish { source: Upload },
+}
+
+#[test]
+fn traces() {
+    fn upload() -> Result<(), Upload> {
+        Err(std::io::Error::other("slow"))?;
+        Ok(())
+    }
+
+    let line = line!();
+    let deploy = upload().trace().context(Publish).unwrap_err();
+    let Deploy::Publish {
+        source: Upload::Stalled { trace, .. },
+        ..
+    } = &deploy;
+    let lines: Vec<_> = trace.locations().iter().map(|l| l.line()).collect();
+    // Made by `?` in `upload`, then passed through here
+    assert_eq!(lines, [line - 4, line + 1]);
+    let report = error::Report::new(&deploy).to_string();
+    assert!(
+        report.ends_with(&format!(
+            "Propagated through: {file}:{}, {file}:{}\n",
+            line - 4,
+            line + 1,
+            file = file!(),
+        )),
+        "{report}"
+    );
+}
+
+#[derive(Error)]
+enum Settings {
+    #[error("read {}: {}", path.display(), reason)]
+    Unreadable {
+        path: std::path::PathBuf,
+        reason: String,
+    },
+    #[error("{name} is out of range", name = name.to_uppercase())]
+    #[help("{name} must be below {max}", max = max + 1)]
+    OutOfRange { name: String, max: u32 },
+}
+
+#[test]
+fn explicit_args() {
+    let unreadable: Settings = Unreadable::new("/etc/app.toml".into(), "denied".to_string());
+    assert_eq!(unreadable.to_string(), "read /etc/app.toml: denied");
+
+    // A named argument shadows the field of the same name, in that text only
+    let out_of_range: Settings = OutOfRange::new("port".to_string(), 9);
+    assert_eq!(out_of_range.to_string(), "PORT is out of range");
+    let help = error::Diagnostic::help(&out_of_range).unwrap();
+    assert_eq!(help.to_string(), "Help: port must be below 10\n");
+}